@@ -8,6 +8,7 @@ import (
 
 	"context"
 	"encoding/base64"
+	"encoding/binary"
 	"encoding/json"
 	"github.com/eteu-technologies/near-api-go/pkg/client"
 	"github.com/eteu-technologies/near-api-go/pkg/client/block"
@@ -28,6 +29,27 @@ var CallBackURL = "https://nmeet.org"
 // AccountID - TODO: env
 var AccountID = "nmeet.near"
 
+// ContractID of the dTelecom contract
+var ContractID = "webrtc.dtelecom.near"
+
+// Network the contract is configured for, see set_network
+var Network = "mainnet"
+
+// Call actions in the order of the contract's CallAction enum
+const (
+	ActionCreate byte = iota
+	ActionCheckpoint
+	ActionEnd
+	ActionJoin
+	ActionLeave
+)
+
+// SignedMessagePrefix starts every signed call approval
+var SignedMessagePrefix = []byte("dtelecom:call")
+
+// SignedMessageVersion of the contract's SignedMessage layout
+const SignedMessageVersion byte = 2
+
 // Participant model
 type Participant struct {
 	gorm.Model `json:"model"`
@@ -48,6 +70,7 @@ type Conference struct {
 	URL        string    `json:"url"`
 	Duration   string    `json:"duration"`
 	CallID     string    `json:"callId"`
+	NodeID     string    `json:"nodeId"`
 }
 
 // ConferenceUser json
@@ -98,12 +121,13 @@ func newConference(db *gorm.DB) func(echo.Context) error {
 			log.Printf("terr: %v", terr)
 		}
 
-		url, err := getNodeURL()
+		url, nodeID, err := getNodeURL()
 		if err == nil {
 			conference := &Conference{
 				SID:    confID,
 				URL:    url,
 				CallID: callID,
+				NodeID: nodeID,
 			}
 			db.Create(&conference)
 
@@ -191,7 +215,7 @@ func addParticipant(db *gorm.DB) func(echo.Context) error {
 
 		epoch, _ := getEpochHeight()
 
-		sig, _ := getConfirmationSignature(conference.CallID, duration, epoch)
+		sig, _ := getConfirmationSignature(ActionCreate, conference.CallID, conference.NodeID, "0", epoch)
 
 		signatureView := &SignatureView{
 			Signature: sig,
@@ -227,7 +251,7 @@ func removeParticipant(db *gorm.DB) func(echo.Context) error {
 
 		epoch, _ := getEpochHeight()
 
-		sig, _ := getConfirmationSignature(conference.CallID, duration, epoch)
+		sig, _ := getConfirmationSignature(ActionCheckpoint, conference.CallID, conference.NodeID, duration, epoch)
 		signatureView := &SignatureView{
 			Signature: sig,
 			Epoch:     epoch,
@@ -252,7 +276,7 @@ func removeConference(db *gorm.DB) func(echo.Context) error {
 
 		epoch, _ := getEpochHeight()
 
-		sig, _ := getConfirmationSignature(conference.CallID, duration, epoch)
+		sig, _ := getConfirmationSignature(ActionEnd, conference.CallID, conference.NodeID, duration, epoch)
 		signatureView := &SignatureView{
 			Signature: sig,
 			Epoch:     epoch,
@@ -300,32 +324,33 @@ type ViewResult struct {
 // GetNodesResult data
 type GetNodesResult struct {
 	Address      string `json:"address"`
+	NodeID       string `json:"node_id"`
 	StakedAmount int64  `json:"staked_amount"`
 	LockedAmount int    `json:"locked_amount"`
 }
 
-func getNodeURL() (string, error) {
+func getNodeURL() (string, string, error) {
 	node := ""
 	keyPair, err := key.NewBase58KeyPair(os.Getenv("NEAR_PK"))
 	if err != nil {
-		return node, fmt.Errorf("key error: %w", err)
+		return node, node, fmt.Errorf("key error: %w", err)
 	}
 
 	network, ok := config.Networks["mainnet"]
 	if !ok {
-		return node, fmt.Errorf("unknown network '%s'", "mainnet")
+		return node, node, fmt.Errorf("unknown network '%s'", "mainnet")
 	}
 
 	rpc, err := client.NewClient(network.NodeURL)
 	if err != nil {
-		return node, fmt.Errorf("failed to create rpc client: %w", err)
+		return node, node, fmt.Errorf("failed to create rpc client: %w", err)
 	}
 
 	ctx := client.ContextWithKeyPair(context.Background(), keyPair)
 
-	res, err := rpc.ContractViewCallFunction(ctx, "webrtc.dtelecom.near", "get_nodes", base64.StdEncoding.EncodeToString([]byte("")), block.FinalityFinal())
+	res, err := rpc.ContractViewCallFunction(ctx, ContractID, "get_nodes", base64.StdEncoding.EncodeToString([]byte("{}")), block.FinalityFinal())
 	if err != nil {
-		return node, fmt.Errorf("failed to view get_nodes: %w", err)
+		return node, node, fmt.Errorf("failed to view get_nodes: %w", err)
 	}
 
 	var viewResult ViewResult
@@ -335,9 +360,13 @@ func getNodeURL() (string, error) {
 	var getNodesResult []GetNodesResult
 	json.Unmarshal(viewResult.Result, &getNodesResult)
 
+	if len(getNodesResult) == 0 {
+		return node, node, fmt.Errorf("no nodes available")
+	}
+
 	randomIndex := rand.Intn(len(getNodesResult))
 
-	return getNodesResult[randomIndex].Address, nil
+	return getNodesResult[randomIndex].Address, getNodesResult[randomIndex].NodeID, nil
 }
 
 func getEpochHeight() (uint64, error) {
@@ -360,7 +389,7 @@ func getEpochHeight() (uint64, error) {
 
 	ctx := client.ContextWithKeyPair(context.Background(), keyPair)
 
-	res, err := rpc.ContractViewCallFunction(ctx, "webrtc.dtelecom.near", "get_epoch_height", base64.StdEncoding.EncodeToString([]byte("")), block.FinalityFinal())
+	res, err := rpc.ContractViewCallFunction(ctx, ContractID, "get_epoch_height", base64.StdEncoding.EncodeToString([]byte("")), block.FinalityFinal())
 	if err != nil {
 		return height, fmt.Errorf("failed to view get_epoch_height: %w", err)
 	}
@@ -396,16 +425,54 @@ func getConferenceData(sid string, uid string, callID string) (string, string, e
 	return string(j), base64.StdEncoding.EncodeToString(sig.Value()), nil
 }
 
-func getConfirmationSignature(callID string, duration string, epoch uint64) (string, error) {
+func getConfirmationSignature(action byte, callID string, nodeID string, duration string, epoch uint64) (string, error) {
 
 	keyPair, err := key.NewBase58KeyPair(os.Getenv("NEAR_PK"))
 	if err != nil {
 		return "", fmt.Errorf("key error: %w", err)
 	}
 
-	message := callID + ":" + duration + ":" + strconv.Itoa(int(epoch))
+	minutes, err := strconv.ParseUint(duration, 10, 64)
+	if err != nil {
+		return "", fmt.Errorf("invalid duration: %w", err)
+	}
+
+	message := signedMessage(action, callID, nodeID, minutes, epoch)
 
 	log.Printf("pubKey: %v", keyPair.PublicKey)
-	sig := keyPair.Sign([]byte(message))
+	sig := keyPair.Sign(message)
 	return base64.StdEncoding.EncodeToString(sig.Value()), nil
 }
+
+// signedMessage builds the bytes the contract verifies a call approval
+// against: SignedMessagePrefix followed by the borsh encoding of its
+// SignedMessage, for calls billed in NEAR.
+func signedMessage(action byte, callID string, nodeID string, minutes uint64, epoch uint64) []byte {
+	message := append([]byte{}, SignedMessagePrefix...)
+	message = append(message, SignedMessageVersion)
+	message = appendString(message, Network)
+	message = appendString(message, ContractID)
+	message = appendString(message, nodeID)
+	message = append(message, action)
+	message = appendString(message, callID)
+	// token_id: None
+	message = append(message, 0)
+	// minutes: u128, the high half is always zero here
+	message = appendUint64(message, minutes)
+	message = appendUint64(message, 0)
+	message = appendUint64(message, epoch)
+	return message
+}
+
+// appendString appends s borsh encoded: a little-endian u32 length and the bytes.
+func appendString(b []byte, s string) []byte {
+	length := make([]byte, 4)
+	binary.LittleEndian.PutUint32(length, uint32(len(s)))
+	return append(append(b, length...), s...)
+}
+
+func appendUint64(b []byte, v uint64) []byte {
+	value := make([]byte, 8)
+	binary.LittleEndian.PutUint64(value, v)
+	return append(b, value...)
+}
\ No newline at end of file
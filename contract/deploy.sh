@@ -5,4 +5,5 @@
 echo ">> Deploying contract"
 
 near deploy --wasmFile ./target/wasm32-unknown-unknown/release/contract.wasm --accountId $CONTRACT_NAME
-near call $CONTRACT_NAME init "{\"owner\": \"$ACCOUNT_ID\"}" --accountId $CONTRACT_NAME
\ No newline at end of file
+near call $CONTRACT_NAME init "{\"owner\": \"$ACCOUNT_ID\"}" --accountId $CONTRACT_NAME
+near call $CONTRACT_NAME set_network "{\"network\": \"$NEAR_ENV\"}" --accountId $ACCOUNT_ID
\ No newline at end of file
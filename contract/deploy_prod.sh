@@ -3,7 +3,15 @@
 . ../.env
 ./build.sh
 
-echo ">> Deploying contract"
+# Upgrades the deployed contract and migrates its state in the same
+# transaction. Pass --init only when deploying to an empty account.
+if [ "$1" = "--init" ]; then
+  echo ">> Deploying contract"
 
-near deploy --wasmFile ./target/wasm32-unknown-unknown/release/contract.wasm --accountId $CONTRACT_NAME
-near call $CONTRACT_NAME init "{\"owner\": \"$ACCOUNT_ID\"}" --accountId $CONTRACT_NAME
\ No newline at end of file
+  near deploy --wasmFile ./target/wasm32-unknown-unknown/release/contract.wasm --accountId $CONTRACT_NAME
+  near call $CONTRACT_NAME init "{\"owner\": \"$ACCOUNT_ID\"}" --accountId $CONTRACT_NAME
+else
+  echo ">> Upgrading contract"
+
+  near deploy --wasmFile ./target/wasm32-unknown-unknown/release/contract.wasm --accountId $CONTRACT_NAME --initFunction migrate --initArgs '{}'
+fi
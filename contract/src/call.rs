@@ -1,4 +1,13 @@
 use crate::*;
+use near_sdk::collections::{LookupMap, UnorderedSet};
+
+/// Prefixes of the per-account sets in `client_calls`, `node_calls` and
+/// `client_active_calls`, followed by the hash of the account id.
+const CLIENT_CALLS_PREFIX: &[u8] = b"L";
+const NODE_CALLS_PREFIX: &[u8] = b"M";
+const CLIENT_ACTIVE_CALLS_PREFIX: &[u8] = b"K";
+
+const NANOS_PER_MINUTE: u64 = 60_000_000_000;
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -6,50 +15,239 @@ pub struct Call {
     pub id: String,
     pub client_id: AccountId,
     pub node_id: AccountId,
+    /// Minutes billed so far, advanced by checkpoints and the final `end_call`.
     pub minutes: u128,
     pub created_at: u64,
     pub ended_at: u64,
+    /// Charged to the client so far.
     pub earned: u128,
+    /// Token every participant is billed in, `None` for NEAR.
+    pub token_id: Option<AccountId>,
+    /// Taken from the client's deposit in `create_call` for the minutes
+    /// still to be billed, the rest goes back to the client at the end.
+    pub escrow: Balance,
+    /// After this anyone can `expire_call` the active call. Checkpoints push
+    /// it back, within `Config::max_call_duration` of `created_at`.
+    pub deadline: u64,
+    /// Until when the ended call can be fined, zero while it is active.
+    pub dispute_expires_at: u64,
+    /// Attendees paying for their own minutes, besides `client_id`.
+    pub participants: Vec<Participant>,
 }
 
-#[near_bindgen]
-impl Contract {
-    pub fn create_call(&mut self, id: String, client_id: String, epoch: EpochHeight, sign: String) {
-        assert!(
-            env::epoch_height() - epoch < 2,
-            "Wrong epoch {}",
-            env::epoch_height()
-        );
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Participant {
+    pub client_id: AccountId,
+    /// Minutes billed so far, across every time the participant joined.
+    pub minutes: u128,
+    pub active: bool,
+    pub joined_at: u64,
+    pub left_at: u64,
+    /// Charged to the participant so far.
+    pub earned: u128,
+}
 
-        let signature =
-            ed25519_dalek::Signature::from_bytes(&bs58::decode(sign).into_vec().unwrap()).unwrap();
+impl Call {
+    /// Minutes `client_id` has been billed for so far, as owner or participant.
+    pub fn billed_minutes(&self, client_id: &AccountId) -> u128 {
+        if self.client_id == *client_id {
+            return self.minutes;
+        }
+        self.participants
+            .iter()
+            .find(|p| p.client_id == *client_id)
+            .map_or(0, |p| p.minutes)
+    }
 
-        let mut node = expect_token_found(self.nodes.get(&env::predecessor_account_id()));
+    /// Whether `client_id` may still be billed for this call.
+    pub fn is_billing(&self, client_id: &AccountId) -> bool {
+        self.client_id == *client_id
+            || self
+                .participants
+                .iter()
+                .any(|p| p.client_id == *client_id && p.active)
+    }
+}
 
-        require!(node.staked_amount == STAKE_AMOUNT, "Not enough staked");
+/// A client-signed minute total, as passed to `checkpoint_call`.
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignedCheckpoint {
+    pub minutes: u128,
+    pub epoch: EpochHeight,
+    pub sign: String,
+}
 
-        let previous_active = self.active_calls.get(&id);
-        assert!(previous_active.is_none() == true, "Call exist");
+/// What one settlement of a call charged and how it was split.
+pub struct Settlement {
+    pub spent: Balance,
+    pub node_earned: Balance,
+    pub protocol_earned: Balance,
+    pub fund_earned: Balance,
+}
 
-        let previous_recent = self.get_recent_call(id.clone());
-        assert!(previous_recent.is_none() == true, "Call exist");
+/// Starts every signed payload, so it can't pass for a transaction or for
+/// another app's message signed with the same key.
+pub const SIGNED_MESSAGE_PREFIX: &[u8] = b"dtelecom:call";
+
+/// Layout of `SignedMessage`, bumped whenever a field is added or changed.
+pub const SIGNED_MESSAGE_VERSION: u8 = 2;
+
+/// What the client approves with a signature.
+#[derive(BorshSerialize, Clone, Copy)]
+pub enum CallAction {
+    Create,
+    Checkpoint,
+    End,
+    Join,
+    Leave,
+}
 
-        let client_account: AccountId = client_id.parse().unwrap();
-        assert!("invalid.".parse::<AccountId>().is_err());
+/// Payload a client signs to approve an action on a call. The signed bytes
+/// are `SIGNED_MESSAGE_PREFIX` followed by the borsh encoding of this struct:
+/// strings and account ids as a u32 length plus bytes, integers little-endian,
+/// `action` as a single byte in declaration order and `token_id` as a zero
+/// byte for NEAR or a one byte followed by the account id.
+#[derive(BorshSerialize)]
+pub struct SignedMessage {
+    pub version: u8,
+    /// `Config::network` of the contract the signature is meant for.
+    pub network: String,
+    pub contract_id: AccountId,
+    pub node_id: AccountId,
+    pub action: CallAction,
+    pub call_id: String,
+    /// Token the call is billed in, `None` for NEAR.
+    pub token_id: Option<AccountId>,
+    /// Signed total, the maximum to escrow for `Create` (zero for
+    /// `Config::default_max_minutes`) and zero for `Join`.
+    pub minutes: u128,
+    pub epoch: EpochHeight,
+}
 
-        let client = expect_token_found(self.clients.get(&client_account));
+impl SignedMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = SIGNED_MESSAGE_PREFIX.to_vec();
+        bytes.extend(self.try_to_vec().unwrap());
+        bytes
+    }
+}
 
-        require!(
-            client.deposited_amount > MINUTE_PRICE * 2,
-            "Not enough client balance"
-        );
+/// Checks that `sign`, a base58 ed25519 signature, was made with `pk` over `message`.
+fn verify_signature(pk: &PublicKey, message: &SignedMessage, sign: &str) {
+    let signature = bs58::decode(sign)
+        .into_vec()
+        .ok()
+        .and_then(|bytes| ed25519_dalek::Signature::from_bytes(&bytes).ok())
+        .unwrap_or_else(|| ContractError::InvalidSignature.panic());
+
+    let public_key = ed25519_dalek::PublicKey::from_bytes(&pk.as_bytes()[1..])
+        .unwrap_or_else(|_| ContractError::InvalidPublicKey.panic());
+
+    ensure(
+        public_key.verify(&message.to_bytes(), &signature).is_ok(),
+        ContractError::SignatureMismatch,
+    );
+}
+
+fn index_call(
+    index: &mut LookupMap<AccountId, UnorderedSet<String>>,
+    prefix: &[u8],
+    account_id: &AccountId,
+    call_id: &String,
+) {
+    let mut calls = index.get(account_id).unwrap_or_else(|| {
+        let mut set_prefix = prefix.to_vec();
+        set_prefix.extend(env::sha256(account_id.as_bytes()));
+        UnorderedSet::new(set_prefix)
+    });
+    calls.insert(call_id);
+    index.insert(account_id, &calls);
+}
+
+fn unindex_call(
+    index: &mut LookupMap<AccountId, UnorderedSet<String>>,
+    account_id: &AccountId,
+    call_id: &String,
+) {
+    if let Some(mut calls) = index.get(account_id) {
+        calls.remove(call_id);
+        if calls.is_empty() {
+            index.remove(account_id);
+        } else {
+            index.insert(account_id, &calls);
+        }
+    }
+}
+
+fn assert_recent_epoch(epoch: EpochHeight) {
+    let current = env::epoch_height();
+    ensure(
+        epoch <= current && current - epoch < 2,
+        ContractError::WrongEpoch { current },
+    );
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Starts a call billed in `token_id`, or in NEAR when omitted, and
+    /// escrows `max_minutes` of the client's deposit for it, or
+    /// `Config::default_max_minutes` when omitted.
+    pub fn create_call(
+        &mut self,
+        id: String,
+        client_id: String,
+        epoch: EpochHeight,
+        sign: String,
+        token_id: Option<AccountId>,
+        max_minutes: Option<u128>,
+    ) {
+        self.assert_not_paused(Subsystem::CallCreation);
 
-        let public_key = ed25519_dalek::PublicKey::from_bytes(&client.pk.as_bytes()[1..]).unwrap();
+        assert_recent_epoch(epoch);
 
-        let message = id.clone() + ":0:" + &epoch.to_string();
-        let verified = public_key.verify(message.as_bytes(), &signature).is_ok();
+        let mut node = self
+            .nodes
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
 
-        require!(verified == true, "Signature mismatch");
+        node.assert_may_create_calls();
+        ensure(node.has_capacity(), ContractError::NodeAtCapacity);
+
+        let previous_active = self.active_calls.get(&id);
+        ensure(previous_active.is_none(), ContractError::CallExists);
+
+        let previous_recent = self.recent_calls.get(&id);
+        ensure(previous_recent.is_none(), ContractError::CallExists);
+
+        let client_account = parse_account_id(&client_id);
+
+        let mut client = self
+            .clients
+            .get(&client_account)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+
+        ensure(max_minutes != Some(0), ContractError::InvalidMaxMinutes);
+        let escrow = max_minutes
+            .unwrap_or(self.config.default_max_minutes)
+            .checked_mul(self.minute_price(token_id.as_ref()))
+            .filter(|escrow| {
+                self.available_balance(&client, &client_account, token_id.as_ref()) >= *escrow
+            })
+            .unwrap_or_else(|| ContractError::NotEnoughClientBalance.panic());
+
+        let message = self.signed_message(
+            CallAction::Create,
+            &id,
+            &token_id,
+            max_minutes.unwrap_or(0),
+            epoch,
+        );
+        verify_signature(&client.pk, &message, &sign);
+
+        *client.balance_mut(token_id.as_ref()) -= escrow;
+        self.clients.insert(&client_account, &client);
 
         let call = Call {
             id: id.clone(),
@@ -59,16 +257,36 @@ impl Contract {
             created_at: env::block_timestamp(),
             ended_at: 0,
             earned: 0,
+            token_id,
+            escrow,
+            deadline: self.call_deadline(env::block_timestamp()),
+            dispute_expires_at: 0,
+            participants: Vec::new(),
         };
 
         self.active_calls.insert(&id, &call);
+        self.index_call(&call);
+        self.index_active_call(&call);
 
         node.unstaked_available_epoch_height = env::epoch_height() + 3;
+        node.active_calls += 1;
         self.nodes.insert(&env::predecessor_account_id(), &node);
 
-        self.total_conferences = self.total_conferences + 1;
+        self.total_conferences += 1;
+
+        Event::CallCreated {
+            call_id: call.id,
+            client_id: call.client_id,
+            node_id: call.node_id,
+            token_id: call.token_id,
+            escrow: U128(escrow),
+        }
+        .emit();
     }
 
+    /// Bills the client for its signed total and closes the call. Every
+    /// participant must have left through `leave_call` first, so none of
+    /// them goes unbilled.
     pub fn end_call(
         &mut self,
         id: String,
@@ -77,138 +295,693 @@ impl Contract {
         epoch: EpochHeight,
         sign: String,
     ) {
-        let signature =
-            ed25519_dalek::Signature::from_bytes(&bs58::decode(sign).into_vec().unwrap()).unwrap();
+        self.assert_not_paused(Subsystem::CallSettlement);
+
+        let mut call = self
+            .active_calls
+            .get(&id)
+            .unwrap_or_else(|| ContractError::CallNotFound.panic());
+        let mut node = self
+            .nodes
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+
+        let client_account = parse_account_id(&client_id);
+
+        ensure(
+            call.client_id == client_account,
+            ContractError::ClientMismatch,
+        );
+        ensure(
+            call.node_id == env::predecessor_account_id(),
+            ContractError::NodeMismatch,
+        );
+        ensure(
+            call.participants.iter().all(|p| !p.active),
+            ContractError::ParticipantsActive,
+        );
+
+        let client = self
+            .clients
+            .get(&client_account)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+
+        let message = self.signed_message(CallAction::End, &id, &call.token_id, minutes, epoch);
+        verify_signature(&client.pk, &message, &sign);
+
+        let settlement = self.settle_minutes(
+            call.token_id.as_ref(),
+            &mut call.minutes,
+            &mut call.earned,
+            &mut call.escrow,
+            &mut node,
+            minutes,
+        );
 
-        let mut call = expect_token_found(self.active_calls.get(&id));
-        let mut node = expect_token_found(self.nodes.get(&env::predecessor_account_id()));
+        let refunded = self.close_call(&mut call, Some(&mut node));
+
+        Event::CallEnded {
+            call_id: call.id,
+            client_id: call.client_id,
+            node_id: call.node_id,
+            minutes: U128(call.minutes),
+            spent: U128(settlement.spent),
+            node_earned: U128(settlement.node_earned),
+            protocol_earned: U128(settlement.protocol_earned),
+            fund_earned: U128(settlement.fund_earned),
+            refunded: U128(refunded),
+        }
+        .emit();
+    }
 
-        let client_account: AccountId = client_id.parse().unwrap();
-        assert!("invalid.".parse::<AccountId>().is_err());
+    /// Bills the client for a signed cumulative minute count while the call
+    /// keeps running, so a node that crashes later still gets paid up to here.
+    pub fn checkpoint_call(&mut self, id: String, minutes: u128, epoch: EpochHeight, sign: String) {
+        self.assert_not_paused(Subsystem::CallSettlement);
 
-        require!(call.client_id == client_account, "Client mismatch");
-        require!(
+        let mut call = self
+            .active_calls
+            .get(&id)
+            .unwrap_or_else(|| ContractError::CallNotFound.panic());
+
+        ensure(
             call.node_id == env::predecessor_account_id(),
-            "Node mismatch"
+            ContractError::NodeMismatch,
         );
 
-        let mut client = expect_token_found(self.clients.get(&client_account));
+        let mut node = self
+            .nodes
+            .get(&call.node_id)
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+        let client = self
+            .clients
+            .get(&call.client_id)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+
+        let message =
+            self.signed_message(CallAction::Checkpoint, &id, &call.token_id, minutes, epoch);
+        verify_signature(&client.pk, &message, &sign);
+
+        let settlement = self.settle_minutes(
+            call.token_id.as_ref(),
+            &mut call.minutes,
+            &mut call.earned,
+            &mut call.escrow,
+            &mut node,
+            minutes,
+        );
 
-        let public_key = ed25519_dalek::PublicKey::from_bytes(&client.pk.as_bytes()[1..]).unwrap();
+        call.deadline = self.call_deadline(call.created_at);
 
-        let message = id.clone() + ":" + &minutes.to_string() + ":" + &epoch.to_string();
-        let verified = public_key.verify(message.as_bytes(), &signature).is_ok();
+        self.nodes.insert(&call.node_id, &node);
+        self.active_calls.insert(&id, &call);
 
-        require!(verified == true, "Signature mismatch");
+        Event::CallCheckpointed {
+            call_id: call.id,
+            client_id: call.client_id,
+            node_id: call.node_id,
+            minutes: U128(call.minutes),
+            spent: U128(settlement.spent),
+            node_earned: U128(settlement.node_earned),
+            protocol_earned: U128(settlement.protocol_earned),
+            fund_earned: U128(settlement.fund_earned),
+        }
+        .emit();
+    }
 
-        call.minutes = minutes;
-        call.ended_at = env::block_timestamp();
+    /// Adds an attendee who pays for their own minutes. Someone who left can
+    /// join again; their signed minute count keeps running across visits.
+    pub fn join_call(&mut self, id: String, client_id: String, epoch: EpochHeight, sign: String) {
+        self.assert_not_paused(Subsystem::CallCreation);
 
-        let spent = call.minutes * MINUTE_PRICE;
+        assert_recent_epoch(epoch);
 
-        let to_spent: u128 = if client.deposited_amount >= spent {
-            spent
-        } else {
-            client.deposited_amount
+        let mut call = self
+            .active_calls
+            .get(&id)
+            .unwrap_or_else(|| ContractError::CallNotFound.panic());
+
+        ensure(
+            call.node_id == env::predecessor_account_id(),
+            ContractError::NodeMismatch,
+        );
+
+        let client_account = parse_account_id(&client_id);
+        ensure(
+            !call.is_billing(&client_account),
+            ContractError::ParticipantExists,
+        );
+
+        let client = self
+            .clients
+            .get(&client_account)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+
+        let token_id = call.token_id.as_ref();
+        ensure(
+            self.available_balance(&client, &client_account, token_id)
+                > self.minute_price(token_id) * 2,
+            ContractError::NotEnoughClientBalance,
+        );
+
+        let message = self.signed_message(CallAction::Join, &id, &call.token_id, 0, epoch);
+        verify_signature(&client.pk, &message, &sign);
+
+        match call
+            .participants
+            .iter_mut()
+            .find(|p| p.client_id == client_account)
+        {
+            Some(participant) => {
+                participant.active = true;
+                participant.joined_at = env::block_timestamp();
+            }
+            None => call.participants.push(Participant {
+                client_id: client_account.clone(),
+                minutes: 0,
+                active: true,
+                joined_at: env::block_timestamp(),
+                left_at: 0,
+                earned: 0,
+            }),
+        }
+
+        self.active_calls.insert(&id, &call);
+        index_call(
+            &mut self.client_calls,
+            CLIENT_CALLS_PREFIX,
+            &client_account,
+            &id,
+        );
+        index_call(
+            &mut self.client_active_calls,
+            CLIENT_ACTIVE_CALLS_PREFIX,
+            &client_account,
+            &id,
+        );
+
+        Event::ParticipantJoined {
+            call_id: call.id,
+            client_id: client_account,
+            node_id: call.node_id,
+        }
+        .emit();
+    }
+
+    /// Bills a participant for their signed total minutes and marks them as left.
+    pub fn leave_call(
+        &mut self,
+        id: String,
+        client_id: String,
+        minutes: u128,
+        epoch: EpochHeight,
+        sign: String,
+    ) {
+        self.assert_not_paused(Subsystem::CallSettlement);
+
+        let mut call = self
+            .active_calls
+            .get(&id)
+            .unwrap_or_else(|| ContractError::CallNotFound.panic());
+
+        ensure(
+            call.node_id == env::predecessor_account_id(),
+            ContractError::NodeMismatch,
+        );
+
+        let client_account = parse_account_id(&client_id);
+
+        let mut node = self
+            .nodes
+            .get(&call.node_id)
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+        let mut client = self
+            .clients
+            .get(&client_account)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+
+        let message = self.signed_message(CallAction::Leave, &id, &call.token_id, minutes, epoch);
+        verify_signature(&client.pk, &message, &sign);
+
+        let index = call
+            .participants
+            .iter()
+            .position(|p| p.client_id == client_account && p.active)
+            .unwrap_or_else(|| ContractError::ParticipantNotFound.panic());
+
+        let participant = &mut call.participants[index];
+        let settlement = self.settle_minutes(
+            call.token_id.as_ref(),
+            &mut participant.minutes,
+            &mut participant.earned,
+            client.balance_mut(call.token_id.as_ref()),
+            &mut node,
+            minutes,
+        );
+        participant.active = false;
+        participant.left_at = env::block_timestamp();
+
+        self.clients.insert(&client_account, &client);
+        self.nodes.insert(&call.node_id, &node);
+        self.active_calls.insert(&id, &call);
+        unindex_call(&mut self.client_active_calls, &client_account, &id);
+
+        Event::ParticipantLeft {
+            call_id: call.id,
+            client_id: client_account,
+            node_id: call.node_id,
+            minutes: U128(minutes),
+            spent: U128(settlement.spent),
+            node_earned: U128(settlement.node_earned),
+            protocol_earned: U128(settlement.protocol_earned),
+            fund_earned: U128(settlement.fund_earned),
+        }
+        .emit();
+    }
+
+    /// Ends a call whose node let its `deadline` pass, callable by anyone.
+    /// The client is billed up to `checkpoint` when given, which must be
+    /// signed for the call's node, and the node is marked as having
+    /// abandoned a call. A call whose node is no longer registered can
+    /// still be expired, but only without a checkpoint, since there is no
+    /// node left to credit.
+    pub fn expire_call(&mut self, id: String, checkpoint: Option<SignedCheckpoint>) {
+        self.assert_not_paused(Subsystem::CallSettlement);
+
+        let mut call = self
+            .active_calls
+            .get(&id)
+            .unwrap_or_else(|| ContractError::CallNotFound.panic());
+
+        ensure(
+            env::block_timestamp() >= call.deadline,
+            ContractError::CallNotExpired,
+        );
+
+        let mut node = self.nodes.get(&call.node_id);
+        let client = self
+            .clients
+            .get(&call.client_id)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+
+        let settlement = match checkpoint {
+            Some(checkpoint) => {
+                let node = node
+                    .as_mut()
+                    .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+                let mut message = self.signed_message(
+                    CallAction::Checkpoint,
+                    &id,
+                    &call.token_id,
+                    checkpoint.minutes,
+                    checkpoint.epoch,
+                );
+                // Submitted by anyone, but still approved for the call's node.
+                message.node_id = call.node_id.clone();
+                verify_signature(&client.pk, &message, &checkpoint.sign);
+
+                self.settle_minutes(
+                    call.token_id.as_ref(),
+                    &mut call.minutes,
+                    &mut call.earned,
+                    &mut call.escrow,
+                    node,
+                    checkpoint.minutes,
+                )
+            }
+            None => Settlement {
+                spent: 0,
+                node_earned: 0,
+                protocol_earned: 0,
+                fund_earned: 0,
+            },
         };
 
-        client.deposited_amount = client.deposited_amount - to_spent;
+        if let Some(node) = node.as_mut() {
+            node.expired_calls += 1;
+        }
 
-        self.clients.insert(&call.client_id, &client);
-        self.active_calls.remove(&id);
+        let refunded = self.close_call(&mut call, node.as_mut());
+
+        Event::CallExpired {
+            call_id: call.id,
+            client_id: call.client_id,
+            node_id: call.node_id,
+            minutes: U128(call.minutes),
+            spent: U128(settlement.spent),
+            node_earned: U128(settlement.node_earned),
+            protocol_earned: U128(settlement.protocol_earned),
+            fund_earned: U128(settlement.fund_earned),
+            refunded: U128(refunded),
+        }
+        .emit();
+    }
+
+    /// Ends a stuck call without billing it and refunds its escrow. It can
+    /// be disputed like any ended call, which is how its node gets fined.
+    pub fn end_active_call(&mut self, id: String) {
+        self.assert_role(Role::FineOperator);
 
-        self.total_minutes = self.total_minutes + minutes;
-        self.total_earned = self.total_earned + to_spent;
+        let mut call = self
+            .active_calls
+            .get(&id)
+            .unwrap_or_else(|| ContractError::CallNotFound.panic());
 
-        let earned = to_spent;
-        call.earned = earned;
+        let mut node = self.nodes.get(&call.node_id);
+        let refunded = self.close_call(&mut call, node.as_mut());
 
-        if earned > 0 {
-            node.earned_amount += earned / 2;
-            self.balance += earned / 2;
+        Event::CallForceEnded {
+            call_id: call.id,
+            client_id: call.client_id,
+            node_id: call.node_id,
+            refunded: U128(refunded),
         }
+        .emit();
+    }
 
-        self.nodes.insert(&env::predecessor_account_id(), &node);
+    pub fn get_active_calls(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Call> {
+        paginate(&self.active_calls, from_index, limit)
+            .into_iter()
+            .map(|(_, call)| call)
+            .collect()
+    }
 
-        let store_key = env::epoch_height() % 2;
+    pub fn get_active_call(&self, id: String) -> Option<Call> {
+        self.active_calls.get(&id)
+    }
+
+    pub fn get_recent_calls(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Call> {
+        paginate(&self.recent_calls, from_index, limit)
+            .into_iter()
+            .map(|(_, call)| call)
+            .collect()
+    }
 
-        if store_key == 0 {
-            if store_key != self.prev_storage_key {
-                self.recent_calls_0.clear();
+    pub fn get_recent_call(&self, id: String) -> Option<Call> {
+        self.recent_calls.get(&id)
+    }
+
+    /// Active and recent calls `client_id` pays for, as owner or participant.
+    pub fn get_client_calls(
+        &self,
+        client_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Call> {
+        self.indexed_calls(&self.client_calls, &client_id, from_index, limit)
+    }
+
+    /// Active and recent calls served by `node_id`.
+    pub fn get_node_calls(
+        &self,
+        node_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Call> {
+        self.indexed_calls(&self.node_calls, &node_id, from_index, limit)
+    }
+
+    /// Drops up to `limit` ended calls whose dispute window is over, oldest
+    /// first. Anyone can call it. Returns how many were dropped.
+    pub fn prune_expired_calls(&mut self, limit: u64) -> u64 {
+        let now = env::block_timestamp();
+        let expired: Vec<(u64, String)> = self
+            .recent_calls_by_expiry
+            .iter()
+            .map(|(key, _)| key)
+            .take_while(|(expires_at, _)| *expires_at <= now)
+            .take(limit as usize)
+            .collect();
+
+        for key in expired.iter() {
+            self.recent_calls_by_expiry.remove(key);
+            if let Some(call) = self.recent_calls.remove(&key.1) {
+                self.unindex_call(&call);
             }
-            self.recent_calls_0.insert(&id, &call);
         }
-        if store_key == 1 {
-            if store_key != self.prev_storage_key {
-                self.recent_calls_1.clear();
+
+        expired.len() as u64
+    }
+
+    /// Bills `minutes`, the client-signed total, minus the `billed` minutes
+    /// already charged. Charges are taken from `funds`, the call's escrow or
+    /// a participant's deposit in `token_id`, and capped at what is left
+    /// there. The shares are split by `Config::node_share_bps` and
+    /// `Config::fund_share_bps` and credited in the same token.
+    fn settle_minutes(
+        &mut self,
+        token_id: Option<&AccountId>,
+        billed: &mut u128,
+        earned: &mut Balance,
+        funds: &mut Balance,
+        node: &mut Node,
+        minutes: u128,
+    ) -> Settlement {
+        ensure(minutes >= *billed, ContractError::MinutesBelowCheckpoint);
+
+        let unbilled = minutes - *billed;
+        let spent = (unbilled * self.minute_price(token_id)).min(*funds);
+
+        *funds -= spent;
+        *billed = minutes;
+        *earned += spent;
+
+        self.total_minutes += unbilled;
+
+        let node_earned = spent * u128::from(self.config.node_share_bps) / MAX_BPS;
+        let fund_earned = spent * u128::from(self.config.fund_share_bps) / MAX_BPS;
+        // Rounding leftovers go to the protocol, so the shares add up to `spent`.
+        let protocol_earned = spent - node_earned - fund_earned;
+
+        *node.earned_mut(token_id) += node_earned;
+        match token_id {
+            Some(token_id) => {
+                let mut token = self.get_whitelisted_token(token_id);
+                token.balance += protocol_earned;
+                token.fund_balance += fund_earned;
+                self.tokens.insert(token_id, &token);
+            }
+            None => {
+                self.total_earned += spent;
+                self.balance += protocol_earned;
+                self.fund_balance += fund_earned;
             }
-            self.recent_calls_1.insert(&id, &call);
         }
 
-        self.prev_storage_key = store_key;
+        Settlement {
+            spent,
+            node_earned,
+            protocol_earned,
+            fund_earned,
+        }
     }
 
-    pub fn end_active_call(&mut self, id: String, fine: Balance) {
-        if self.owner != near_sdk::env::predecessor_account_id() {
-            near_sdk::env::panic_str("Method method is private");
+    /// What the client must have signed for `action` when the calling node
+    /// submits it to this contract.
+    fn signed_message(
+        &self,
+        action: CallAction,
+        id: &str,
+        token_id: &Option<AccountId>,
+        minutes: u128,
+        epoch: EpochHeight,
+    ) -> SignedMessage {
+        SignedMessage {
+            version: SIGNED_MESSAGE_VERSION,
+            network: self.config.network.clone(),
+            contract_id: env::current_account_id(),
+            node_id: env::predecessor_account_id(),
+            action,
+            call_id: id.to_string(),
+            token_id: token_id.clone(),
+            minutes,
+            epoch,
         }
+    }
 
-        if fine > 0 {
-            let call = expect_token_found(self.active_calls.get(&id));
-            let mut node = expect_token_found(self.nodes.get(&call.node_id));
-            let mut client = expect_token_found(self.clients.get(&call.client_id));
-            node.staked_amount = node.staked_amount - fine;
-            client.deposited_amount = client.deposited_amount + fine;
-            self.nodes.insert(&call.node_id, &node);
-            self.clients.insert(&call.client_id, &client);
+    /// When a call created at `created_at` can be expired if it isn't
+    /// checkpointed again from now.
+    fn call_deadline(&self, created_at: u64) -> u64 {
+        let max_end = created_at + self.config.max_call_duration;
+        match self.config.heartbeat_timeout {
+            0 => max_end,
+            timeout => max_end.min(env::block_timestamp() + timeout),
+        }
+    }
+
+    /// Moves a settled call from `active_calls` to the recent calls, releases
+    /// its node, if still registered, keeping its stake until the call can't
+    /// be disputed, and refunds its escrow. Returns the refunded amount.
+    fn close_call(&mut self, call: &mut Call, node: Option<&mut Node>) -> Balance {
+        call.ended_at = env::block_timestamp();
+
+        // Only expired or force-ended calls get here with participants left:
+        // they stay unbilled from their last settlement.
+        for participant in call.participants.iter_mut().filter(|p| p.active) {
+            participant.active = false;
+            participant.left_at = call.ended_at;
+        }
+
+        call.dispute_expires_at = call.ended_at + self.config.dispute_window;
+
+        if let Some(node) = node {
+            node.active_calls = node.active_calls.saturating_sub(1);
+            node.disputable_until = node.disputable_until.max(call.dispute_expires_at);
+            self.nodes.insert(&call.node_id, node);
         }
 
-        self.active_calls.remove(&id);
+        self.active_calls.remove(&call.id);
+        self.unindex_active_call(call);
+        let refunded = self.refund_escrow(call);
+        self.insert_recent_call(call);
+
+        refunded
     }
 
-    pub fn fine_recent_call(&mut self, id: String, fine: Balance) {
-        if self.owner != near_sdk::env::predecessor_account_id() {
-            near_sdk::env::panic_str("Method method is private");
+    /// Credits what is left of the call's escrow back to its client.
+    fn refund_escrow(&mut self, call: &mut Call) -> Balance {
+        let refunded = std::mem::take(&mut call.escrow);
+        if refunded > 0 {
+            let mut client = self
+                .clients
+                .get(&call.client_id)
+                .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+            *client.balance_mut(call.token_id.as_ref()) += refunded;
+            self.clients.insert(&call.client_id, &client);
         }
+        refunded
+    }
 
-        let call = self
-            .get_recent_call(id)
-            .unwrap_or_else(|| env::panic_str("Call not found"));
+    /// Whether `client_id` is billed in any active call.
+    pub(crate) fn is_client_in_call(&self, client_id: &AccountId) -> bool {
+        self.client_active_calls.contains_key(client_id)
+    }
 
-        let mut node = expect_token_found(self.nodes.get(&call.node_id));
-        let mut client = expect_token_found(self.clients.get(&call.client_id));
-        node.staked_amount = node.staked_amount - fine;
-        client.deposited_amount = client.deposited_amount + fine;
-        self.nodes.insert(&call.node_id, &node);
-        self.clients.insert(&call.client_id, &client);
+    /// What the active calls `client_id` joined in `token_id` could still
+    /// charge its deposit: every minute up to `Config::max_call_duration`
+    /// that wasn't billed yet. Calls it created are paid from their escrow.
+    pub(crate) fn reserved_balance(
+        &self,
+        client_id: &AccountId,
+        token_id: Option<&AccountId>,
+    ) -> Balance {
+        let calls = match self.client_active_calls.get(client_id) {
+            Some(calls) => calls,
+            None => return 0,
+        };
+        let max_minutes = u128::from(self.config.max_call_duration.div_ceil(NANOS_PER_MINUTE));
+        let minute_price = self.minute_price(token_id);
+
+        calls
+            .iter()
+            .filter_map(|id| self.active_calls.get(&id))
+            .filter(|call| call.token_id.as_ref() == token_id && call.client_id != *client_id)
+            .map(|call| max_minutes.saturating_sub(call.billed_minutes(client_id)) * minute_price)
+            .sum()
     }
 
-    pub fn get_active_calls(&self) -> Vec<Call> {
-        self.active_calls.values().collect()
+    /// What of `client`'s deposit in `token_id` no active call could charge.
+    pub(crate) fn available_balance(
+        &self,
+        client: &Client,
+        client_id: &AccountId,
+        token_id: Option<&AccountId>,
+    ) -> Balance {
+        client
+            .balance(token_id)
+            .saturating_sub(self.reserved_balance(client_id, token_id))
     }
 
-    pub fn get_active_call(&self, id: String) -> Option<Call> {
-        self.active_calls.get(&id)
+    fn indexed_calls(
+        &self,
+        index: &LookupMap<AccountId, UnorderedSet<String>>,
+        account_id: &AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Call> {
+        let (from_index, limit) = page(from_index, limit);
+        let ids = match index.get(account_id) {
+            Some(calls) => calls,
+            None => return Vec::new(),
+        };
+        let ids = ids.as_vector();
+        let to_index = from_index.saturating_add(limit).min(ids.len());
+
+        (from_index..to_index)
+            .filter_map(|index| {
+                let id = ids.get(index).unwrap();
+                self.active_calls
+                    .get(&id)
+                    .or_else(|| self.recent_calls.get(&id))
+            })
+            .collect()
     }
 
-    pub fn get_recent_calls_0(&self) -> Vec<Call> {
-        self.recent_calls_0.values().collect()
+    /// Lists `call` under its client, participants and node.
+    pub(crate) fn index_call(&mut self, call: &Call) {
+        index_call(
+            &mut self.client_calls,
+            CLIENT_CALLS_PREFIX,
+            &call.client_id,
+            &call.id,
+        );
+        for participant in call.participants.iter() {
+            index_call(
+                &mut self.client_calls,
+                CLIENT_CALLS_PREFIX,
+                &participant.client_id,
+                &call.id,
+            );
+        }
+        index_call(
+            &mut self.node_calls,
+            NODE_CALLS_PREFIX,
+            &call.node_id,
+            &call.id,
+        );
     }
 
-    pub fn get_recent_calls_1(&self) -> Vec<Call> {
-        self.recent_calls_1.values().collect()
+    fn unindex_call(&mut self, call: &Call) {
+        unindex_call(&mut self.client_calls, &call.client_id, &call.id);
+        for participant in call.participants.iter() {
+            unindex_call(&mut self.client_calls, &participant.client_id, &call.id);
+        }
+        unindex_call(&mut self.node_calls, &call.node_id, &call.id);
     }
 
-    fn get_recent_call(&mut self, id: String) -> Option<Call> {
-        let call0 = self.recent_calls_0.get(&id);
-        if call0.is_none() == false {
-            return call0;
-        } else {
-            let call1 = self.recent_calls_1.get(&id);
-            if call1.is_none() == false {
-                return call1;
-            }
+    /// Lists `call` under its client and active participants, for as long
+    /// as they are billed in it.
+    pub(crate) fn index_active_call(&mut self, call: &Call) {
+        index_call(
+            &mut self.client_active_calls,
+            CLIENT_ACTIVE_CALLS_PREFIX,
+            &call.client_id,
+            &call.id,
+        );
+        for participant in call.participants.iter().filter(|p| p.active) {
+            index_call(
+                &mut self.client_active_calls,
+                CLIENT_ACTIVE_CALLS_PREFIX,
+                &participant.client_id,
+                &call.id,
+            );
         }
-        None
+    }
+
+    fn unindex_active_call(&mut self, call: &Call) {
+        unindex_call(&mut self.client_active_calls, &call.client_id, &call.id);
+        for participant in call.participants.iter() {
+            unindex_call(
+                &mut self.client_active_calls,
+                &participant.client_id,
+                &call.id,
+            );
+        }
+    }
+
+    pub(crate) fn insert_recent_call(&mut self, call: &Call) {
+        self.recent_calls.insert(&call.id, call);
+        self.recent_calls_by_expiry
+            .insert(&(call.dispute_expires_at, call.id.clone()), &());
     }
 }
@@ -1,72 +1,205 @@
 use crate::*;
+use std::collections::HashMap;
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Client {
     pub deposited_amount: Balance,
     pub pk: PublicKey,
+    /// Deposits in whitelisted tokens, see `ft_on_transfer`.
+    pub token_balances: HashMap<AccountId, Balance>,
+}
+
+impl Client {
+    /// Deposit in `token_id`, or in NEAR for `None`.
+    pub fn balance(&self, token_id: Option<&AccountId>) -> Balance {
+        match token_id {
+            Some(token_id) => self.token_balances.get(token_id).copied().unwrap_or(0),
+            None => self.deposited_amount,
+        }
+    }
+
+    pub fn balance_mut(&mut self, token_id: Option<&AccountId>) -> &mut Balance {
+        match token_id {
+            Some(token_id) => self.token_balances.entry(token_id.clone()).or_insert(0),
+            None => &mut self.deposited_amount,
+        }
+    }
 }
 
 #[near_bindgen]
 impl Contract {
     #[payable]
     pub fn add_balance(&mut self) {
+        self.assert_not_paused(Subsystem::Deposits);
+
         let deposit_amount: Balance = env::attached_deposit();
         let client = self.clients.get(&env::predecessor_account_id());
 
-        let deposited: Balance = if client.is_none() == true {
-            0
-        } else {
-            client.as_ref().unwrap().deposited_amount
-        };
+        let deposited: Balance = client.as_ref().map_or(0, |c| c.deposited_amount);
 
-        let to_deposit: Balance = if client.is_none() == true {
-            assert!(
-                deposit_amount > STORAGE_COST,
-                "Deposit more than {} yoctoNEAR",
-                STORAGE_COST
+        let to_deposit: Balance = if client.is_none() {
+            ensure(
+                deposit_amount > self.config.storage_cost,
+                ContractError::DepositTooLow {
+                    min: self.config.storage_cost,
+                },
             );
-            deposit_amount - STORAGE_COST
+            deposit_amount - self.config.storage_cost
         } else {
             deposit_amount
         };
 
-        let pk: PublicKey = if client.is_none() == true {
-            env::signer_account_pk()
-        } else {
-            client.as_ref().unwrap().pk.clone()
-        };
+        let (pk, token_balances) = client.map_or_else(
+            || (env::signer_account_pk(), HashMap::new()),
+            |c| (c.pk, c.token_balances),
+        );
 
         let new_client = Client {
             deposited_amount: deposited + to_deposit,
-            pk: pk,
+            pk,
+            token_balances,
         };
 
         self.clients
             .insert(&env::predecessor_account_id(), &new_client);
+
+        Event::ClientDeposit {
+            client_id: env::predecessor_account_id(),
+            amount: U128(to_deposit),
+            deposited_amount: U128(new_client.deposited_amount),
+            token_id: None,
+        }
+        .emit();
     }
 
     pub fn get_client(&self, account: AccountId) -> Option<Client> {
         self.clients.get(&account)
     }
 
-    pub fn withdraw_balance(&mut self) -> Promise {
-        let mut client = expect_token_found(self.clients.get(&env::predecessor_account_id()));
+    pub fn get_clients(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, Client)> {
+        paginate(&self.clients, from_index, limit)
+    }
+
+    /// Withdraws the whole deposit in `token_id`, or in NEAR when omitted.
+    pub fn withdraw_balance(&mut self, token_id: Option<AccountId>) -> Promise {
+        self.assert_not_paused(Subsystem::Withdrawals);
 
-        assert_ne!(client.deposited_amount, 0, "Low deposit");
+        let mut client = self
+            .clients
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
 
-        let current_calls: Vec<Call> = self
-            .active_calls
-            .values()
-            .filter(|c| c.client_id == env::predecessor_account_id())
-            .collect();
-        require!(current_calls.len() == 0, "Deposit must be unused");
+        ensure(
+            client.balance(token_id.as_ref()) != 0,
+            ContractError::LowDeposit,
+        );
 
-        let to_withdraw = client.deposited_amount;
-        client.deposited_amount = 0;
+        ensure(
+            !self.is_client_in_call(&env::predecessor_account_id()),
+            ContractError::DepositInUse,
+        );
+
+        let to_withdraw = std::mem::take(client.balance_mut(token_id.as_ref()));
 
         self.clients.insert(&env::predecessor_account_id(), &client);
 
-        Promise::new(env::predecessor_account_id()).transfer(to_withdraw)
+        self.transfer_withdrawal(token_id, to_withdraw)
+    }
+
+    /// Withdraws `amount` of the deposit in `token_id`, or in NEAR when
+    /// omitted. Unlike `withdraw_balance` it works during calls, as long as
+    /// what is left covers what they could still charge.
+    pub fn withdraw_balance_amount(
+        &mut self,
+        amount: U128,
+        token_id: Option<AccountId>,
+    ) -> Promise {
+        self.assert_not_paused(Subsystem::Withdrawals);
+
+        ensure(amount.0 > 0, ContractError::InvalidAmount);
+
+        let client_id = env::predecessor_account_id();
+        let mut client = self
+            .clients
+            .get(&client_id)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+
+        let available = self.available_balance(&client, &client_id, token_id.as_ref());
+        ensure(
+            amount.0 <= available,
+            ContractError::WithdrawAboveAvailable { available },
+        );
+
+        *client.balance_mut(token_id.as_ref()) -= amount.0;
+        self.clients.insert(&client_id, &client);
+
+        self.transfer_withdrawal(token_id, amount.0)
+    }
+
+    /// Credits a failed withdrawal back to the client. Returns whether the
+    /// transfer went through.
+    #[private]
+    pub fn resolve_withdraw_balance(
+        &mut self,
+        client_id: AccountId,
+        token_id: Option<AccountId>,
+        amount: U128,
+    ) -> bool {
+        if promise_succeeded(0) {
+            Event::ClientWithdrawPaid {
+                client_id,
+                amount,
+                token_id,
+            }
+            .emit();
+            return true;
+        }
+
+        let mut client = self
+            .clients
+            .get(&client_id)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+        *client.balance_mut(token_id.as_ref()) += amount.0;
+        self.clients.insert(&client_id, &client);
+
+        Event::ClientWithdrawRestored {
+            client_id,
+            amount,
+            token_id,
+        }
+        .emit();
+
+        false
+    }
+}
+
+impl Contract {
+    /// Sends `amount`, already taken from the caller's deposit in `token_id`,
+    /// and credits it back if the transfer fails.
+    fn transfer_withdrawal(&mut self, token_id: Option<AccountId>, amount: Balance) -> Promise {
+        let client_id = env::predecessor_account_id();
+
+        Event::ClientWithdraw {
+            client_id: client_id.clone(),
+            amount: U128(amount),
+            token_id: token_id.clone(),
+        }
+        .emit();
+
+        let transfer = match token_id.clone() {
+            Some(token_id) => ft_transfer(token_id, client_id.clone(), amount),
+            None => Promise::new(client_id.clone()).transfer(amount),
+        };
+
+        transfer.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .resolve_withdraw_balance(client_id, token_id, U128(amount)),
+        )
     }
 }
@@ -0,0 +1,206 @@
+use crate::*;
+
+// 0.001 NEAR
+pub const DEFAULT_STORAGE_COST: Balance = 1_000_000_000_000_000_000_000;
+
+// 0.001 NEAR ~ 0.002 USD
+pub const DEFAULT_MINUTE_PRICE: Balance = 1_000_000_000_000_000_000_000;
+
+// 10 NEAR
+pub const DEFAULT_STAKE_AMOUNT: Balance = 10_000_000_000_000_000_000_000_000;
+
+// 24 hours
+pub const DEFAULT_DISPUTE_WINDOW: u64 = 86_400_000_000_000;
+
+pub const DEFAULT_NETWORK: &str = "mainnet";
+
+// 30 days, as node stakes stay locked for the window after each call
+pub const MAX_DISPUTE_WINDOW: u64 = 2_592_000_000_000_000;
+
+// 24 hours
+pub const DEFAULT_MAX_CALL_DURATION: u64 = 86_400_000_000_000;
+
+// 2 hours, escrowed by calls created without a signed maximum
+pub const DEFAULT_MAX_MINUTES: u128 = 120;
+
+// 1 NEAR, a tenth of the default stake
+pub const DEFAULT_MAX_FINE: Balance = 1_000_000_000_000_000_000_000_000;
+
+// 0.01 NEAR
+pub const DEFAULT_DISPUTE_BOND: Balance = 10_000_000_000_000_000_000_000;
+
+// 7 days
+pub const DEFAULT_DISPUTE_TIMEOUT: u64 = 604_800_000_000_000;
+
+// Half of every charge, the rest goes to the protocol
+pub const DEFAULT_NODE_SHARE_BPS: u16 = 5_000;
+
+/// Basis points making up a whole charge.
+pub const MAX_BPS: u128 = 10_000;
+
+// Disabled, nodes aren't required to checkpoint
+pub const DEFAULT_HEARTBEAT_TIMEOUT: u64 = 0;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Config {
+    /// Kept from the first deposit of a new client to cover its storage.
+    pub storage_cost: Balance,
+    /// Billed per call minute.
+    pub minute_price: Balance,
+    /// Exact deposit required from a new node in `add_node`.
+    pub stake_amount: Balance,
+    /// Nanoseconds an ended call can still be fined for.
+    pub dispute_window: u64,
+    /// Signed into every client approval, see `SignedMessage`.
+    pub network: String,
+    /// Nanoseconds after creation a call can be expired by anyone.
+    pub max_call_duration: u64,
+    /// Nanoseconds a call can go without a checkpoint before it can be
+    /// expired by anyone. Zero disables it.
+    pub heartbeat_timeout: u64,
+    /// Minutes escrowed by `create_call` when the client didn't sign a maximum.
+    pub default_max_minutes: u128,
+    /// Basis points of every charge credited to the node.
+    pub node_share_bps: u16,
+    /// Basis points of every charge credited to `fund_id`. The protocol
+    /// gets what neither the node nor the fund takes.
+    pub fund_share_bps: u16,
+    /// Third recipient of charges, such as a referral or ecosystem fund.
+    pub fund_id: Option<AccountId>,
+    /// Most a node can be fined for one call.
+    pub max_fine: Balance,
+    /// Least a client must lock to open a dispute.
+    pub dispute_bond: Balance,
+    /// Nanoseconds a resolver has to settle a dispute before anyone can
+    /// close it with `expire_dispute`.
+    pub dispute_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            storage_cost: DEFAULT_STORAGE_COST,
+            minute_price: DEFAULT_MINUTE_PRICE,
+            stake_amount: DEFAULT_STAKE_AMOUNT,
+            dispute_window: DEFAULT_DISPUTE_WINDOW,
+            network: DEFAULT_NETWORK.to_string(),
+            max_call_duration: DEFAULT_MAX_CALL_DURATION,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            default_max_minutes: DEFAULT_MAX_MINUTES,
+            node_share_bps: DEFAULT_NODE_SHARE_BPS,
+            fund_share_bps: 0,
+            fund_id: None,
+            max_fine: DEFAULT_MAX_FINE,
+            dispute_bond: DEFAULT_DISPUTE_BOND,
+            dispute_timeout: DEFAULT_DISPUTE_TIMEOUT,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_storage_cost(&mut self, storage_cost: U128) {
+        self.assert_role(Role::Admin);
+        self.config.storage_cost = storage_cost.0;
+    }
+
+    pub fn set_minute_price(&mut self, minute_price: U128) {
+        self.assert_role(Role::Admin);
+        ensure(minute_price.0 > 0, ContractError::InvalidConfigValue);
+        self.config.minute_price = minute_price.0;
+    }
+
+    /// Only applies to nodes registered from now on, see `Node::is_staked`.
+    pub fn set_stake_amount(&mut self, stake_amount: U128) {
+        self.assert_role(Role::Admin);
+        ensure(stake_amount.0 > 0, ContractError::InvalidConfigValue);
+        self.config.stake_amount = stake_amount.0;
+    }
+
+    /// Only applies to calls ending from now on.
+    pub fn set_dispute_window(&mut self, dispute_window: U64) {
+        self.assert_role(Role::Admin);
+        ensure(dispute_window.0 > 0, ContractError::InvalidConfigValue);
+        ensure(
+            dispute_window.0 <= MAX_DISPUTE_WINDOW,
+            ContractError::DisputeWindowAboveMax {
+                max: MAX_DISPUTE_WINDOW,
+            },
+        );
+        self.config.dispute_window = dispute_window.0;
+    }
+
+    /// Invalidates every signature clients made for the previous network.
+    pub fn set_network(&mut self, network: String) {
+        self.assert_role(Role::Admin);
+        ensure(!network.is_empty(), ContractError::InvalidConfigValue);
+        self.config.network = network;
+    }
+
+    /// Only applies to calls created or checkpointed from now on.
+    pub fn set_max_call_duration(&mut self, max_call_duration: U64) {
+        self.assert_role(Role::Admin);
+        ensure(max_call_duration.0 > 0, ContractError::InvalidConfigValue);
+        self.config.max_call_duration = max_call_duration.0;
+    }
+
+    /// Only applies to calls created or checkpointed from now on.
+    pub fn set_heartbeat_timeout(&mut self, heartbeat_timeout: U64) {
+        self.assert_role(Role::Admin);
+        self.config.heartbeat_timeout = heartbeat_timeout.0;
+    }
+
+    /// Only applies to calls created from now on.
+    pub fn set_default_max_minutes(&mut self, default_max_minutes: U128) {
+        self.assert_role(Role::Admin);
+        ensure(default_max_minutes.0 > 0, ContractError::InvalidConfigValue);
+        self.config.default_max_minutes = default_max_minutes.0;
+    }
+
+    /// Only applies to charges from now on. Fund shares collected so far
+    /// are paid to the new `fund_id` by `withdraw_fund`.
+    pub fn set_revenue_split(
+        &mut self,
+        node_share_bps: u16,
+        fund_share_bps: u16,
+        fund_id: Option<AccountId>,
+    ) {
+        self.assert_role(Role::Admin);
+        ensure(
+            u128::from(node_share_bps) + u128::from(fund_share_bps) <= MAX_BPS,
+            ContractError::InvalidRevenueSplit,
+        );
+        ensure(
+            fund_share_bps == 0 || fund_id.is_some(),
+            ContractError::FundNotSet,
+        );
+        self.config.node_share_bps = node_share_bps;
+        self.config.fund_share_bps = fund_share_bps;
+        self.config.fund_id = fund_id;
+    }
+
+    pub fn set_max_fine(&mut self, max_fine: U128) {
+        self.assert_role(Role::Admin);
+        ensure(max_fine.0 > 0, ContractError::InvalidConfigValue);
+        self.config.max_fine = max_fine.0;
+    }
+
+    /// Only applies to disputes opened from now on.
+    pub fn set_dispute_bond(&mut self, dispute_bond: U128) {
+        self.assert_role(Role::Admin);
+        ensure(dispute_bond.0 > 0, ContractError::InvalidConfigValue);
+        self.config.dispute_bond = dispute_bond.0;
+    }
+
+    /// Only applies to disputes opened from now on.
+    pub fn set_dispute_timeout(&mut self, dispute_timeout: U64) {
+        self.assert_role(Role::Admin);
+        ensure(dispute_timeout.0 > 0, ContractError::InvalidConfigValue);
+        self.config.dispute_timeout = dispute_timeout.0;
+    }
+
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+}
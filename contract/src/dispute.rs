@@ -0,0 +1,237 @@
+use crate::*;
+
+/// A client's claim against the node of one of its recent calls, waiting
+/// for a resolver. One per call and client, so each participant can
+/// dispute what it was billed.
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Dispute {
+    pub call_id: String,
+    /// Who opened the dispute, the call's client or one of its participants.
+    pub client_id: AccountId,
+    pub node_id: AccountId,
+    /// Hash of the client's evidence, kept off-chain.
+    pub evidence_hash: String,
+    /// Hash of the node's answer, kept off-chain.
+    pub response_hash: Option<String>,
+    /// Taken from the client's NEAR deposit, returned if the dispute is upheld.
+    pub bond: Balance,
+    pub opened_at: u64,
+    /// When anyone can close the dispute with `expire_dispute` if no
+    /// resolver settled it.
+    pub resolve_by: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Disputes a recent call of the caller while its dispute window is open,
+    /// locking `bond` of its NEAR deposit. The node can't be removed until
+    /// the dispute is resolved or, after `Config::dispute_timeout`, expired.
+    pub fn open_dispute(&mut self, call_id: String, evidence_hash: String, bond: U128) {
+        let client_id = env::predecessor_account_id();
+
+        let call = self
+            .recent_calls
+            .get(&call_id)
+            .unwrap_or_else(|| ContractError::CallNotFound.panic());
+
+        ensure(
+            env::block_timestamp() < call.dispute_expires_at,
+            ContractError::DisputeWindowClosed,
+        );
+        ensure(
+            call.client_id == client_id
+                || call.participants.iter().any(|p| p.client_id == client_id),
+            ContractError::ClientMismatch,
+        );
+        let key = (call_id.clone(), client_id.clone());
+        ensure(
+            self.disputes.get(&key).is_none(),
+            ContractError::DisputeExists,
+        );
+        ensure(
+            bond.0 >= self.config.dispute_bond,
+            ContractError::BondTooLow {
+                min: self.config.dispute_bond,
+            },
+        );
+
+        let mut client = self
+            .clients
+            .get(&client_id)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+        ensure(
+            client.deposited_amount >= bond.0,
+            ContractError::NotEnoughClientBalance,
+        );
+        let mut node = self
+            .nodes
+            .get(&call.node_id)
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+
+        client.deposited_amount -= bond.0;
+        node.open_disputes += 1;
+        self.clients.insert(&client_id, &client);
+        self.nodes.insert(&call.node_id, &node);
+
+        let dispute = Dispute {
+            call_id,
+            client_id,
+            node_id: call.node_id,
+            evidence_hash,
+            response_hash: None,
+            bond: bond.0,
+            opened_at: env::block_timestamp(),
+            resolve_by: env::block_timestamp() + self.config.dispute_timeout,
+        };
+        self.disputes.insert(&key, &dispute);
+
+        Event::DisputeOpened {
+            call_id: dispute.call_id,
+            client_id: dispute.client_id,
+            node_id: dispute.node_id,
+            evidence_hash: dispute.evidence_hash,
+            bond,
+        }
+        .emit();
+    }
+
+    /// The disputed node's answer to `client_id`'s dispute, given once.
+    pub fn respond_dispute(
+        &mut self,
+        call_id: String,
+        client_id: AccountId,
+        response_hash: String,
+    ) {
+        let key = (call_id, client_id);
+        let mut dispute = self
+            .disputes
+            .get(&key)
+            .unwrap_or_else(|| ContractError::DisputeNotFound.panic());
+
+        ensure(
+            dispute.node_id == env::predecessor_account_id(),
+            ContractError::NodeMismatch,
+        );
+        ensure(
+            dispute.response_hash.is_none(),
+            ContractError::DisputeResponded,
+        );
+
+        dispute.response_hash = Some(response_hash.clone());
+        self.disputes.insert(&key, &dispute);
+
+        Event::DisputeResponded {
+            call_id: dispute.call_id,
+            client_id: dispute.client_id,
+            node_id: dispute.node_id,
+            response_hash,
+        }
+        .emit();
+    }
+
+    /// Settles `client_id`'s dispute. If `upheld`, it gets its bond back plus
+    /// `fine` from the node's stake, at most `Config::max_fine` and no more
+    /// than is staked, see `Node::slash`. Otherwise
+    /// the bond goes to the protocol balance and `fine` must be zero.
+    pub fn resolve_dispute(
+        &mut self,
+        call_id: String,
+        client_id: AccountId,
+        upheld: bool,
+        fine: U128,
+    ) {
+        self.assert_role(Role::Resolver);
+
+        let dispute = self
+            .disputes
+            .remove(&(call_id, client_id))
+            .unwrap_or_else(|| ContractError::DisputeNotFound.panic());
+
+        let max_fine = if upheld { self.config.max_fine } else { 0 };
+        ensure(
+            fine.0 <= max_fine,
+            ContractError::FineAboveMax { max: max_fine },
+        );
+
+        let mut node = self
+            .nodes
+            .get(&dispute.node_id)
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+        node.open_disputes -= 1;
+
+        let mut fined = 0;
+        if upheld {
+            let mut client = self
+                .clients
+                .get(&dispute.client_id)
+                .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+            fined = self.slash_node(&mut node, fine.0);
+            client.deposited_amount += dispute.bond + fined;
+            self.clients.insert(&dispute.client_id, &client);
+        } else {
+            self.balance += dispute.bond;
+        }
+
+        self.nodes.insert(&dispute.node_id, &node);
+
+        Event::DisputeResolved {
+            call_id: dispute.call_id,
+            client_id: dispute.client_id,
+            node_id: dispute.node_id,
+            upheld,
+            fine: U128(fined),
+            bond: U128(dispute.bond),
+        }
+        .emit();
+    }
+
+    /// Closes a dispute no resolver settled by its `resolve_by`, callable
+    /// by anyone. Nobody is found at fault: the client gets its bond back
+    /// and the node is free to leave.
+    pub fn expire_dispute(&mut self, call_id: String, client_id: AccountId) {
+        let key = (call_id, client_id);
+        let dispute = self
+            .disputes
+            .get(&key)
+            .unwrap_or_else(|| ContractError::DisputeNotFound.panic());
+
+        ensure(
+            env::block_timestamp() >= dispute.resolve_by,
+            ContractError::DisputeNotExpired,
+        );
+
+        self.disputes.remove(&key);
+
+        if let Some(mut node) = self.nodes.get(&dispute.node_id) {
+            node.open_disputes = node.open_disputes.saturating_sub(1);
+            self.nodes.insert(&dispute.node_id, &node);
+        }
+
+        let mut client = self
+            .clients
+            .get(&dispute.client_id)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+        client.deposited_amount += dispute.bond;
+        self.clients.insert(&dispute.client_id, &client);
+
+        Event::DisputeExpired {
+            call_id: dispute.call_id,
+            client_id: dispute.client_id,
+            node_id: dispute.node_id,
+            bond: U128(dispute.bond),
+        }
+        .emit();
+    }
+
+    pub fn get_disputes(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Dispute> {
+        paginate(&self.disputes, from_index, limit)
+            .into_iter()
+            .map(|(_, dispute)| dispute)
+            .collect()
+    }
+
+    pub fn get_dispute(&self, call_id: String, client_id: AccountId) -> Option<Dispute> {
+        self.disputes.get(&(call_id, client_id))
+    }
+}
@@ -0,0 +1,207 @@
+use crate::*;
+use std::fmt;
+
+/// Every way a contract call can fail. Calls abort with `"E<code>: <message>"`;
+/// codes are matched by the signer and the frontend, so never renumber or
+/// reuse one. They are grouped by hundreds: general, signatures, clients,
+/// nodes, calls and tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContractError {
+    NotOwner,
+    LowBalance,
+    InvalidConfigValue,
+    StateNotFound,
+    UnknownStateVersion,
+    InvalidRevenueSplit,
+    FundNotSet,
+    MissingRole { role: Role },
+    NotProposedOwner,
+    Paused { subsystem: Subsystem },
+    PauseCooldown,
+    InvalidAccountId,
+    InvalidSignature,
+    InvalidPublicKey,
+    SignatureMismatch,
+    WrongEpoch { current: EpochHeight },
+    ClientNotFound,
+    DepositTooLow { min: Balance },
+    LowDeposit,
+    DepositInUse,
+    NotEnoughClientBalance,
+    WithdrawAboveAvailable { available: Balance },
+    InvalidAmount,
+    NodeNotFound,
+    NodeExists,
+    AddressExists,
+    WrongStake { expected: Balance },
+    NotEnoughStaked,
+    NodeInUse,
+    NodeInactive,
+    InvalidNodeMetadata { field: &'static str },
+    NodeAtCapacity,
+    NodeJailed,
+    CallNotFound,
+    CallExists,
+    ClientMismatch,
+    NodeMismatch,
+    MinutesBelowCheckpoint,
+    ParticipantExists,
+    ParticipantNotFound,
+    DisputeWindowClosed,
+    CallNotExpired,
+    FineAboveMax { max: Balance },
+    DisputeNotFound,
+    DisputeExists,
+    BondTooLow { min: Balance },
+    DisputeResponded,
+    InvalidMaxMinutes,
+    DisputeNotExpired,
+    ParticipantsActive,
+    DisputeWindowAboveMax { max: u64 },
+    TokenNotWhitelisted,
+    TokenExists,
+}
+
+impl ContractError {
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::NotOwner => 1,
+            Self::LowBalance => 2,
+            Self::InvalidConfigValue => 3,
+            Self::StateNotFound => 4,
+            Self::UnknownStateVersion => 5,
+            Self::InvalidRevenueSplit => 6,
+            Self::FundNotSet => 7,
+            Self::MissingRole { .. } => 8,
+            Self::NotProposedOwner => 9,
+            Self::Paused { .. } => 10,
+            Self::PauseCooldown => 11,
+            Self::InvalidAccountId => 100,
+            Self::InvalidSignature => 101,
+            Self::InvalidPublicKey => 102,
+            Self::SignatureMismatch => 103,
+            Self::WrongEpoch { .. } => 104,
+            Self::ClientNotFound => 200,
+            Self::DepositTooLow { .. } => 201,
+            Self::LowDeposit => 202,
+            Self::DepositInUse => 203,
+            Self::NotEnoughClientBalance => 204,
+            Self::WithdrawAboveAvailable { .. } => 205,
+            Self::InvalidAmount => 206,
+            Self::NodeNotFound => 300,
+            Self::NodeExists => 301,
+            Self::AddressExists => 302,
+            Self::WrongStake { .. } => 303,
+            Self::NotEnoughStaked => 304,
+            Self::NodeInUse => 305,
+            Self::NodeInactive => 306,
+            Self::InvalidNodeMetadata { .. } => 307,
+            Self::NodeAtCapacity => 308,
+            Self::NodeJailed => 309,
+            Self::CallNotFound => 400,
+            Self::CallExists => 401,
+            Self::ClientMismatch => 402,
+            Self::NodeMismatch => 403,
+            Self::MinutesBelowCheckpoint => 404,
+            Self::ParticipantExists => 405,
+            Self::ParticipantNotFound => 406,
+            Self::DisputeWindowClosed => 407,
+            Self::CallNotExpired => 408,
+            Self::FineAboveMax { .. } => 409,
+            Self::DisputeNotFound => 410,
+            Self::DisputeExists => 411,
+            Self::BondTooLow { .. } => 412,
+            Self::DisputeResponded => 413,
+            Self::InvalidMaxMinutes => 414,
+            Self::DisputeNotExpired => 415,
+            Self::ParticipantsActive => 416,
+            Self::DisputeWindowAboveMax { .. } => 417,
+            Self::TokenNotWhitelisted => 500,
+            Self::TokenExists => 501,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::NotOwner => "Method is private".to_string(),
+            Self::LowBalance => "Low balance".to_string(),
+            Self::InvalidConfigValue => "Value must be positive".to_string(),
+            Self::StateNotFound => "Contract state not found".to_string(),
+            Self::UnknownStateVersion => "Unknown state version".to_string(),
+            Self::InvalidRevenueSplit => "Shares exceed 10000 basis points".to_string(),
+            Self::FundNotSet => "Fund account not set".to_string(),
+            Self::MissingRole { role } => format!("Missing role {:?}", role),
+            Self::NotProposedOwner => "Not the proposed owner".to_string(),
+            Self::Paused { subsystem } => format!("{:?} is paused", subsystem),
+            Self::PauseCooldown => "Withdrawals were paused too recently".to_string(),
+            Self::InvalidAccountId => "Malformed account id".to_string(),
+            Self::InvalidSignature => "Malformed signature".to_string(),
+            Self::InvalidPublicKey => "Client key is not ed25519".to_string(),
+            Self::SignatureMismatch => "Signature mismatch".to_string(),
+            Self::WrongEpoch { current } => format!("Wrong epoch {}", current),
+            Self::ClientNotFound => "Client not found".to_string(),
+            Self::DepositTooLow { min } => format!("Deposit more than {} yoctoNEAR", min),
+            Self::LowDeposit => "Low deposit".to_string(),
+            Self::DepositInUse => "Deposit must be unused".to_string(),
+            Self::NotEnoughClientBalance => "Not enough client balance".to_string(),
+            Self::WithdrawAboveAvailable { available } => {
+                format!("Withdraw at most {} not reserved by calls", available)
+            }
+            Self::InvalidAmount => "Amount must be positive".to_string(),
+            Self::NodeNotFound => "Node not found".to_string(),
+            Self::NodeExists => "Node exists".to_string(),
+            Self::AddressExists => "Address exists".to_string(),
+            Self::WrongStake { expected } => format!("Deposit exact {} yoctoNEAR", expected),
+            Self::NotEnoughStaked => "Not enough staked".to_string(),
+            Self::NodeInUse => "Node must be unused in calls and disputes".to_string(),
+            Self::NodeInactive => "Node is deactivated".to_string(),
+            Self::InvalidNodeMetadata { field } => format!("Invalid node metadata {}", field),
+            Self::NodeAtCapacity => "Node is at max concurrent calls".to_string(),
+            Self::NodeJailed => "Node is jailed".to_string(),
+            Self::CallNotFound => "Call not found".to_string(),
+            Self::CallExists => "Call exists".to_string(),
+            Self::ClientMismatch => "Client mismatch".to_string(),
+            Self::NodeMismatch => "Node mismatch".to_string(),
+            Self::MinutesBelowCheckpoint => "Minutes below last checkpoint".to_string(),
+            Self::ParticipantExists => "Participant already in call".to_string(),
+            Self::ParticipantNotFound => "Participant not in call".to_string(),
+            Self::DisputeWindowClosed => "Dispute window is over".to_string(),
+            Self::CallNotExpired => "Call deadline not reached".to_string(),
+            Self::FineAboveMax { max } => format!("Fine more than {} yoctoNEAR", max),
+            Self::DisputeNotFound => "Dispute not found".to_string(),
+            Self::DisputeExists => "Call already disputed by client".to_string(),
+            Self::BondTooLow { min } => format!("Bond at least {} yoctoNEAR", min),
+            Self::DisputeResponded => "Node already responded".to_string(),
+            Self::InvalidMaxMinutes => "Max minutes must be positive".to_string(),
+            Self::DisputeNotExpired => "Dispute deadline not reached".to_string(),
+            Self::ParticipantsActive => "Participants must leave first".to_string(),
+            Self::DisputeWindowAboveMax { max } => format!("Dispute window more than {} ns", max),
+            Self::TokenNotWhitelisted => "Token not whitelisted".to_string(),
+            Self::TokenExists => "Token already whitelisted".to_string(),
+        }
+    }
+
+    /// Aborts the call. The mocked host used by unit tests can't unwind out
+    /// of `env::panic_str`, so off-chain this is a plain panic.
+    pub fn panic(self) -> ! {
+        let message = self.to_string();
+        if cfg!(target_arch = "wasm32") {
+            env::panic_str(&message)
+        } else {
+            panic!("{}", message)
+        }
+    }
+}
+
+impl fmt::Display for ContractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "E{:03}: {}", self.code(), self.message())
+    }
+}
+
+/// Aborts with `error` unless `condition` holds.
+pub(crate) fn ensure(condition: bool, error: ContractError) {
+    if !condition {
+        error.panic()
+    }
+}
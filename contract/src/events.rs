@@ -0,0 +1,293 @@
+use crate::*;
+use near_sdk::serde_json;
+use std::collections::HashMap;
+
+const EVENT_STANDARD: &str = "dtelecom";
+const EVENT_VERSION: &str = "1.0.0";
+
+/// NEP-297 events, logged as `EVENT_JSON:{"standard":"dtelecom",...}`.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    /// `token_id` is omitted for NEAR, as in every event that has it.
+    ClientDeposit {
+        client_id: AccountId,
+        amount: U128,
+        deposited_amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    ClientWithdraw {
+        client_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// The transfer of a `client_withdraw` went through.
+    ClientWithdrawPaid {
+        client_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// The transfer of a `client_withdraw` failed and was credited back.
+    ClientWithdrawRestored {
+        client_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    NodeRegistered {
+        node_id: AccountId,
+        address: String,
+        staked_amount: U128,
+    },
+    NodeRemoved {
+        node_id: AccountId,
+        staked_amount: U128,
+        earned_amount: U128,
+        /// Paid out alongside `earned_amount`, by token.
+        #[serde(skip_serializing_if = "HashMap::is_empty")]
+        earned_tokens: HashMap<AccountId, U128>,
+    },
+    /// Every payout of a `node_removed` went through.
+    NodeRemovalPaid {
+        node_id: AccountId,
+        staked_amount: U128,
+        earned_amount: U128,
+        #[serde(skip_serializing_if = "HashMap::is_empty")]
+        earned_tokens: HashMap<AccountId, U128>,
+    },
+    /// Some payouts of a `node_removed` failed. The node is back, inactive,
+    /// holding what couldn't be paid.
+    NodeRestored {
+        node_id: AccountId,
+        staked_amount: U128,
+        earned_amount: U128,
+        #[serde(skip_serializing_if = "HashMap::is_empty")]
+        earned_tokens: HashMap<AccountId, U128>,
+    },
+    NodeUpdated {
+        node_id: AccountId,
+        address: String,
+    },
+    /// `state` is the node's state after the slash.
+    NodeSlashed {
+        node_id: AccountId,
+        amount: U128,
+        staked_amount: U128,
+        state: NodeState,
+    },
+    NodeStakeToppedUp {
+        node_id: AccountId,
+        amount: U128,
+        staked_amount: U128,
+        state: NodeState,
+    },
+    NodeActivated {
+        node_id: AccountId,
+    },
+    NodeDeactivated {
+        node_id: AccountId,
+    },
+    NodeEarningsClaimed {
+        node_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// The payout of a claim went through.
+    NodeEarningsPaid {
+        node_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// The payout of a claim failed and was credited back.
+    NodeEarningsRestored {
+        node_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// Amounts in every later event of the call are in its `token_id`.
+    CallCreated {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+        escrow: U128,
+    },
+    /// Amounts are what this checkpoint charged, `minutes` is the call total.
+    CallCheckpointed {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        minutes: U128,
+        spent: U128,
+        node_earned: U128,
+        protocol_earned: U128,
+        fund_earned: U128,
+    },
+    /// Amounts are what the final settlement charged, `minutes` is the call
+    /// total and `refunded` the unused escrow returned to the client.
+    CallEnded {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        minutes: U128,
+        spent: U128,
+        node_earned: U128,
+        protocol_earned: U128,
+        fund_earned: U128,
+        refunded: U128,
+    },
+    /// Amounts are what the signed checkpoint, if any, charged.
+    CallExpired {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        minutes: U128,
+        spent: U128,
+        node_earned: U128,
+        protocol_earned: U128,
+        fund_earned: U128,
+        refunded: U128,
+    },
+    ParticipantJoined {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+    },
+    /// Amounts are what leaving charged, `minutes` is the participant's total.
+    ParticipantLeft {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        minutes: U128,
+        spent: U128,
+        node_earned: U128,
+        protocol_earned: U128,
+        fund_earned: U128,
+    },
+    CallForceEnded {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        refunded: U128,
+    },
+    DisputeOpened {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        evidence_hash: String,
+        bond: U128,
+    },
+    DisputeResponded {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        response_hash: String,
+    },
+    /// `bond` went back to the client if `upheld`, to the protocol otherwise.
+    DisputeResolved {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        upheld: bool,
+        fine: U128,
+        bond: U128,
+    },
+    /// Closed by `expire_dispute`, `bond` went back to the client.
+    DisputeExpired {
+        call_id: String,
+        client_id: AccountId,
+        node_id: AccountId,
+        bond: U128,
+    },
+    OwnerWithdraw {
+        owner_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// The transfer of an `owner_withdraw` went through.
+    OwnerWithdrawPaid {
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// The transfer of an `owner_withdraw` failed and was put back.
+    OwnerWithdrawRestored {
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    OwnershipProposed {
+        owner_id: AccountId,
+        proposed_owner_id: AccountId,
+    },
+    OwnershipTransferred {
+        previous_owner_id: AccountId,
+        owner_id: AccountId,
+    },
+    RoleGranted {
+        account_id: AccountId,
+        role: Role,
+    },
+    RoleRevoked {
+        account_id: AccountId,
+        role: Role,
+    },
+    SubsystemPaused {
+        subsystem: Subsystem,
+        account_id: AccountId,
+    },
+    SubsystemUnpaused {
+        subsystem: Subsystem,
+        account_id: AccountId,
+    },
+    FundWithdraw {
+        fund_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// The transfer of a `fund_withdraw` went through.
+    FundWithdrawPaid {
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+    /// The transfer of a `fund_withdraw` failed and was put back.
+    FundWithdrawRestored {
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token_id: Option<AccountId>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl Event {
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_VERSION,
+            event: self,
+        };
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            serde_json::to_string(&log).unwrap()
+        ));
+    }
+}
@@ -3,67 +3,127 @@ extern crate hex;
 use ed25519_dalek::Verifier;
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
+use near_sdk::collections::{LookupMap, TreeMap, UnorderedMap, UnorderedSet};
 use near_sdk::json_types::{U128, U64};
-use near_sdk::serde::Serialize;
-use near_sdk::{env, near_bindgen, require, AccountId, Balance, EpochHeight, Promise, PublicKey};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, near_bindgen, AccountId, Balance, EpochHeight, Gas, PanicOnDefault, Promise,
+    PromiseResult, PublicKey,
+};
 
 mod call;
 mod client;
+mod config;
+mod dispute;
+mod error;
+mod events;
+mod migration;
 mod node;
+mod pause;
+mod role;
+mod token;
 
 use crate::call::*;
 use crate::client::*;
+use crate::config::*;
+use crate::dispute::*;
+use crate::error::*;
+use crate::events::*;
+use crate::migration::*;
 use crate::node::*;
+use crate::pause::*;
+use crate::role::*;
+use crate::token::*;
 
 type WrappedCounter = U128;
 type WrappedLength = U64;
 
-// 0.001 NEAR
-pub const STORAGE_COST: u128 = 1_000_000_000_000_000_000_000;
+/// Left for a `resolve_*` callback that settles a transfer's outcome.
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(10_000_000_000_000);
 
-// 0.001 NEAR ~ 0.002 USD
-pub const MINUTE_PRICE: u128 = 1_000_000_000_000_000_000_000;
+/// Page size of list views called without a `limit`.
+pub const DEFAULT_PAGE_LIMIT: u64 = 50;
+/// Largest page a list view returns, whatever `limit` asks for.
+pub const MAX_PAGE_LIMIT: u64 = 100;
 
-// 10 NEAR
-pub const STAKE_AMOUNT: u128 = 10_000_000_000_000_000_000_000_000;
+fn parse_account_id(account_id: &str) -> AccountId {
+    account_id
+        .parse()
+        .unwrap_or_else(|_| ContractError::InvalidAccountId.panic())
+}
+
+/// The page a list view was asked for: from the start by default, and
+/// `DEFAULT_PAGE_LIMIT` entries but never more than `MAX_PAGE_LIMIT`.
+fn page(from_index: Option<u64>, limit: Option<u64>) -> (u64, u64) {
+    (
+        from_index.unwrap_or(0),
+        limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT),
+    )
+}
+
+/// Up to `limit` entries of `map` from position `from_index`, in storage
+/// order, see `page`. Stable between calls unless entries are removed,
+/// which moves the last entry into the freed position.
+fn paginate<K, V>(
+    map: &UnorderedMap<K, V>,
+    from_index: Option<u64>,
+    limit: Option<u64>,
+) -> Vec<(K, V)>
+where
+    K: BorshSerialize + BorshDeserialize,
+    V: BorshSerialize + BorshDeserialize,
+{
+    let (from_index, limit) = page(from_index, limit);
+    let keys = map.keys_as_vector();
+    let values = map.values_as_vector();
+    let to_index = from_index.saturating_add(limit).min(keys.len());
+
+    (from_index..to_index)
+        .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+        .collect()
+}
 
-fn expect_token_found<T>(option: Option<T>) -> T {
-    option.unwrap_or_else(|| env::panic_str("Value not found"))
+/// Whether the `index`-th promise a callback was chained to succeeded.
+fn promise_succeeded(index: u64) -> bool {
+    matches!(env::promise_result(index), PromiseResult::Successful(_))
 }
 
 #[near_bindgen]
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
     pub clients: UnorderedMap<AccountId, Client>,
     pub nodes: UnorderedMap<AccountId, Node>,
     pub active_calls: UnorderedMap<String, Call>,
-    pub recent_calls_0: UnorderedMap<String, Call>,
-    pub recent_calls_1: UnorderedMap<String, Call>,
+    /// Ended calls that can still be fined.
+    pub recent_calls: UnorderedMap<String, Call>,
+    /// Keys of `recent_calls` ordered by when their dispute window ends.
+    pub recent_calls_by_expiry: TreeMap<(u64, String), ()>,
     pub balance: Balance,
+    /// Fund share collected in NEAR, see `Config::fund_id`.
+    pub fund_balance: Balance,
     pub owner: AccountId,
+    /// Account `propose_owner` named, until it accepts.
+    pub proposed_owner: Option<AccountId>,
     pub total_earned: Balance,
     pub total_conferences: u128,
     pub total_minutes: u128,
-    pub prev_storage_key: EpochHeight,
-}
-
-impl Default for Contract {
-    fn default() -> Self {
-        Self {
-            clients: UnorderedMap::new(b"c"),
-            nodes: UnorderedMap::new(b"n"),
-            active_calls: UnorderedMap::new(b"a"),
-            recent_calls_0: UnorderedMap::new(b"f"),
-            recent_calls_1: UnorderedMap::new(b"s"),
-            balance: 0,
-            owner: "dtelecom.near".parse().unwrap(),
-            total_earned: 0,
-            total_conferences: 0,
-            total_minutes: 0,
-            prev_storage_key: 0,
-        }
-    }
+    pub config: Config,
+    /// Whitelisted NEP-141 tokens clients can pay in.
+    pub tokens: UnorderedMap<AccountId, Token>,
+    /// Ids of the active and recent calls each client pays for.
+    pub client_calls: LookupMap<AccountId, UnorderedSet<String>>,
+    /// Ids of the active and recent calls each node serves.
+    pub node_calls: LookupMap<AccountId, UnorderedSet<String>>,
+    /// Ids of the active calls each client is billed in, so deposit checks
+    /// don't walk `client_calls`.
+    pub client_active_calls: LookupMap<AccountId, UnorderedSet<String>>,
+    /// Roles granted to accounts other than the owner.
+    pub roles: UnorderedMap<AccountId, Vec<Role>>,
+    /// Subsystems stopped during an incident, see `pause`.
+    pub paused: PauseState,
+    /// Disputes waiting for a resolver, keyed by call and disputing client,
+    /// since every participant of a call can dispute it.
+    pub disputes: UnorderedMap<(String, AccountId), Dispute>,
 }
 
 #[near_bindgen]
@@ -71,31 +131,154 @@ impl Contract {
     #[init]
     #[private]
     pub fn init(owner: AccountId) -> Self {
+        write_state_version();
         Self {
             clients: UnorderedMap::new(b"c"),
             nodes: UnorderedMap::new(b"n"),
             active_calls: UnorderedMap::new(b"a"),
-            recent_calls_0: UnorderedMap::new(b"f"),
-            recent_calls_1: UnorderedMap::new(b"s"),
+            recent_calls: UnorderedMap::new(b"r"),
+            recent_calls_by_expiry: TreeMap::new(b"e"),
             balance: 0,
-            owner: owner,
+            fund_balance: 0,
+            owner,
+            proposed_owner: None,
             total_earned: 0,
             total_conferences: 0,
             total_minutes: 0,
-            prev_storage_key: 0,
+            config: Config::default(),
+            tokens: UnorderedMap::new(b"t"),
+            client_calls: LookupMap::new(b"l"),
+            node_calls: LookupMap::new(b"m"),
+            client_active_calls: LookupMap::new(b"k"),
+            roles: UnorderedMap::new(b"o"),
+            paused: PauseState::default(),
+            disputes: UnorderedMap::new(b"d"),
+        }
+    }
+
+    /// Withdraws the protocol share collected in `token_id`, or in NEAR when
+    /// omitted, to the owner.
+    pub fn withdraw(&mut self, token_id: Option<AccountId>) -> Promise {
+        self.assert_role(Role::Treasurer);
+        self.assert_not_paused(Subsystem::Withdrawals);
+
+        let to_transfer = match &token_id {
+            Some(token_id) => {
+                let mut token = self.get_whitelisted_token(token_id);
+                let balance = std::mem::take(&mut token.balance);
+                self.tokens.insert(token_id, &token);
+                balance
+            }
+            None => std::mem::take(&mut self.balance),
+        };
+
+        ensure(to_transfer > 0, ContractError::LowBalance);
+
+        Event::OwnerWithdraw {
+            owner_id: self.owner.clone(),
+            amount: U128(to_transfer),
+            token_id: token_id.clone(),
+        }
+        .emit();
+
+        let transfer = match token_id.clone() {
+            Some(token_id) => ft_transfer(token_id, self.owner.clone(), to_transfer),
+            None => Promise::new(self.owner.clone()).transfer(to_transfer),
+        };
+
+        transfer.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .resolve_withdraw(token_id, U128(to_transfer)),
+        )
+    }
+
+    /// Puts a failed `withdraw` back into the protocol balance. Returns
+    /// whether the transfer went through.
+    #[private]
+    pub fn resolve_withdraw(&mut self, token_id: Option<AccountId>, amount: U128) -> bool {
+        if promise_succeeded(0) {
+            Event::OwnerWithdrawPaid { amount, token_id }.emit();
+            return true;
+        }
+
+        match &token_id {
+            Some(token_id) => {
+                let mut token = self.get_whitelisted_token(token_id);
+                token.balance += amount.0;
+                self.tokens.insert(token_id, &token);
+            }
+            None => self.balance += amount.0,
+        }
+
+        Event::OwnerWithdrawRestored { amount, token_id }.emit();
+
+        false
+    }
+
+    /// Sends the fund share collected in `token_id`, or in NEAR when omitted,
+    /// to `Config::fund_id`. Anyone can call it.
+    pub fn withdraw_fund(&mut self, token_id: Option<AccountId>) -> Promise {
+        self.assert_not_paused(Subsystem::Withdrawals);
+
+        let fund_id = self
+            .config
+            .fund_id
+            .clone()
+            .unwrap_or_else(|| ContractError::FundNotSet.panic());
+
+        let to_transfer = match &token_id {
+            Some(token_id) => {
+                let mut token = self.get_whitelisted_token(token_id);
+                let balance = std::mem::take(&mut token.fund_balance);
+                self.tokens.insert(token_id, &token);
+                balance
+            }
+            None => std::mem::take(&mut self.fund_balance),
+        };
+
+        ensure(to_transfer > 0, ContractError::LowBalance);
+
+        Event::FundWithdraw {
+            fund_id: fund_id.clone(),
+            amount: U128(to_transfer),
+            token_id: token_id.clone(),
         }
+        .emit();
+
+        let transfer = match token_id.clone() {
+            Some(token_id) => ft_transfer(token_id, fund_id, to_transfer),
+            None => Promise::new(fund_id).transfer(to_transfer),
+        };
+
+        transfer.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .resolve_withdraw_fund(token_id, U128(to_transfer)),
+        )
     }
 
-    pub fn withdraw(&mut self) -> Promise {
-        if self.owner != near_sdk::env::predecessor_account_id() {
-            near_sdk::env::panic_str("Method method is private");
+    /// Puts a failed `withdraw_fund` back into the fund balance. Returns
+    /// whether the transfer went through.
+    #[private]
+    pub fn resolve_withdraw_fund(&mut self, token_id: Option<AccountId>, amount: U128) -> bool {
+        if promise_succeeded(0) {
+            Event::FundWithdrawPaid { amount, token_id }.emit();
+            return true;
+        }
+
+        match &token_id {
+            Some(token_id) => {
+                let mut token = self.get_whitelisted_token(token_id);
+                token.fund_balance += amount.0;
+                self.tokens.insert(token_id, &token);
+            }
+            None => self.fund_balance += amount.0,
         }
 
-        require!(self.balance > 0, "Low balance");
+        Event::FundWithdrawRestored { amount, token_id }.emit();
 
-        let to_transfer = self.balance;
-        self.balance = 0;
-        Promise::new(self.owner.clone()).transfer(to_transfer)
+        false
     }
 
     pub fn get_total_earned(&self) -> WrappedCounter {
@@ -129,28 +312,73 @@ mod tests {
     use ed25519_dalek::Keypair;
     use ed25519_dalek::Signer;
     use hex::FromHex;
-    use near_sdk::test_utils::VMContextBuilder;
-    use near_sdk::testing_env;
+    use near_sdk::test_utils::{get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig, VMConfig};
 
-    const NEAR: u128 = 1000000000000000000000000;
+    const NEAR: u128 = 1_000_000_000_000_000_000_000_000;
+    const CONTRACT_ID: &str = "contract.dtelecom";
+    // 12 hours, the length of a mainnet epoch
+    const EPOCH_DURATION: u64 = 43_200_000_000_000;
 
     #[test]
     fn balance_flow() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        set_context("client_a", 1 * NEAR, 1);
+        set_context("client_a", NEAR, 1);
 
         contract.add_balance();
         let client_a = contract.get_client("client_a".parse().unwrap()).unwrap();
-        assert_eq!(client_a.deposited_amount, 1 * NEAR - STORAGE_COST);
+        assert_eq!(client_a.deposited_amount, NEAR - DEFAULT_STORAGE_COST);
 
-        contract.withdraw_balance();
+        contract.withdraw_balance(None);
         let client_a2 = contract.get_client("client_a".parse().unwrap()).unwrap();
         assert_eq!(client_a2.deposited_amount, 0);
 
         contract.add_balance();
         let client_a = contract.get_client("client_a".parse().unwrap()).unwrap();
-        assert_eq!(client_a.deposited_amount, 1 * NEAR);
+        assert_eq!(client_a.deposited_amount, NEAR);
+    }
+
+    #[test]
+    fn balance_withdraw_failed() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+        contract.withdraw_balance(None);
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        let withdrawn = contract.resolve_withdraw_balance(
+            "client_a".parse().unwrap(),
+            None,
+            U128(NEAR - DEFAULT_STORAGE_COST),
+        );
+        assert!(!withdrawn);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"client_withdraw_restored","data":{"client_id":"client_a","amount":"999000000000000000000000"}}"#
+            ]
+        );
+
+        let client_a = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client_a.deposited_amount, NEAR - DEFAULT_STORAGE_COST);
+
+        set_context("client_a", 0, 1);
+        contract.withdraw_balance(None);
+
+        set_promise_results(vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.resolve_withdraw_balance(
+            "client_a".parse().unwrap(),
+            None,
+            U128(NEAR - DEFAULT_STORAGE_COST),
+        ));
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"client_withdraw_paid","data":{"client_id":"client_a","amount":"999000000000000000000000"}}"#
+            ]
+        );
     }
 
     #[test]
@@ -158,366 +386,3155 @@ mod tests {
     fn balance_panic() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        set_context("client_b", 1 * NEAR, 1);
-        contract.withdraw_balance();
+        set_context("client_b", NEAR, 1);
+        contract.withdraw_balance(None);
     }
 
     #[test]
-    fn node_flow() {
+    fn pagination_flow() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        set_context("mainer_a", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
-        let node = &contract.get_nodes()[0];
-        assert_eq!(node.staked_amount, 10 * NEAR);
-        assert_eq!(node.address, "https://example.com/");
+        for client in ["client_a", "client_b", "client_c"] {
+            set_context(client, NEAR, 1);
+            contract.add_balance();
+        }
 
-        contract.remove_node();
-        let nodes = contract.get_nodes();
-        assert_eq!(nodes.len(), 0);
+        let clients = contract.get_clients(Some(0), Some(2));
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[0].0, "client_a".parse().unwrap());
+        assert_eq!(clients[1].0, "client_b".parse().unwrap());
 
-        contract.add_node("https://example2.com/".to_string());
-        let node2 = &contract.get_nodes()[0];
-        assert_eq!(node2.staked_amount, 10 * NEAR);
-        assert_eq!(node2.address, "https://example2.com/");
+        let clients = contract.get_clients(Some(2), Some(2));
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].0, "client_c".parse().unwrap());
+        assert_eq!(clients[0].1.deposited_amount, NEAR - DEFAULT_STORAGE_COST);
+
+        assert_eq!(contract.get_clients(Some(5), Some(2)).len(), 0);
+
+        for (node, address) in [
+            ("mainer_a", "https://a.example.com/"),
+            ("mainer_b", "https://b.example.com/"),
+        ] {
+            set_context(node, 10 * NEAR, 1);
+            contract.add_node(address.to_string(), node_metadata("eu-west"));
+        }
+
+        let nodes = contract.get_nodes(Some(1), Some(10));
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].node_id, "mainer_b".parse().unwrap());
+        assert_eq!(
+            contract.get_nodes(Some(0), Some(1))[0].node_id,
+            "mainer_a".parse().unwrap()
+        );
+        assert_eq!(contract.get_nodes(None, None).len(), 2);
     }
 
     #[test]
-    #[should_panic]
-    fn node_flow_panic() {
+    fn pagination_default_and_max_limit() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        set_context("mainer_a", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
+        for index in 0..=MAX_PAGE_LIMIT {
+            set_context(&format!("client_{}", index), NEAR, 1);
+            contract.add_balance();
+        }
 
-        set_context("mainer_b", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
+        assert_eq!(
+            contract.get_clients(None, None).len() as u64,
+            DEFAULT_PAGE_LIMIT
+        );
+        assert_eq!(
+            contract.get_clients(None, Some(1_000)).len() as u64,
+            MAX_PAGE_LIMIT
+        );
+        assert_eq!(contract.get_clients(Some(MAX_PAGE_LIMIT), None).len(), 1);
     }
 
     #[test]
-    #[should_panic]
-    fn node_panic() {
+    fn node_flow() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        set_context("mainer_a", 1 * NEAR, 1);
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+        let node = &contract.get_nodes(Some(0), Some(100))[0];
+        assert_eq!(node.staked_amount, 10 * NEAR);
+        assert_eq!(node.address, "https://example.com/");
+
         contract.remove_node();
+        let nodes = contract.get_nodes(Some(0), Some(100));
+        assert_eq!(nodes.len(), 0);
+
+        contract.add_node(
+            "https://example2.com/".to_string(),
+            node_metadata("eu-west"),
+        );
+        let node2 = &contract.get_nodes(Some(0), Some(100))[0];
+        assert_eq!(node2.staked_amount, 10 * NEAR);
+        assert_eq!(node2.address, "https://example2.com/");
     }
 
     #[test]
-    fn call_flow() {
+    fn node_claim_earnings() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
         let keypair: Keypair = prepare_keypair();
 
-        set_context("client_a", 1 * NEAR, 1);
+        set_context("client_a", NEAR, 1);
         contract.add_balance();
 
         set_context("mainer_a", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
 
-        let signature1 = keypair.sign(b"123:0:1");
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
         contract.create_call(
             "123".to_string(),
             "client_a".to_string(),
             1,
-            bs58::encode(signature1).into_string(),
+            signature1,
+            None,
+            None,
         );
 
-        let calls1 = contract.get_active_calls();
-        assert_eq!(calls1.len(), 1);
-
-        let call_started = &calls1[0];
-        assert_eq!(call_started.id, "123");
-        assert_eq!(call_started.client_id, "client_a".parse().unwrap());
-        assert_eq!(call_started.node_id, "mainer_a".parse().unwrap());
-
-        let signature2 = keypair.sign(b"123:100:1");
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
         contract.end_call(
             "123".to_string(),
             "client_a".to_string(),
             100,
             1,
-            bs58::encode(signature2).into_string(),
+            signature2,
         );
-        let calls2 = contract.get_active_calls();
-        assert_eq!(calls2.len(), 0);
 
-        assert_eq!(contract.balance, 500_000_000_000_000_000_000_00);
+        contract.claim_earnings(None);
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.earned_amount, 0);
+        assert_eq!(node.staked_amount, 10 * NEAR);
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 1);
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        let claimed =
+            contract.resolve_claim_earnings(node, None, U128(50_000_000_000_000_000_000_000));
+        assert!(!claimed);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"node_earnings_restored","data":{"node_id":"mainer_a","amount":"50000000000000000000000"}}"#
+            ]
+        );
 
-        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
-        assert_eq!(client.deposited_amount, 8_990_000_000_000_000_000_000_00);
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.earned_amount, 50_000_000_000_000_000_000_000);
+
+        set_context("mainer_a", 0, 1);
+        contract.claim_earnings(None);
+
+        set_promise_results(vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.resolve_claim_earnings(node, None, U128(50_000_000_000_000_000_000_000)));
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"node_earnings_paid","data":{"node_id":"mainer_a","amount":"50000000000000000000000"}}"#
+            ]
+        );
 
         let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.earned_amount, 0);
+    }
+
+    #[test]
+    fn node_claim_earnings_failed_after_remove() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
 
-        assert_eq!(node.earned_amount, 500_000_000_000_000_000_000_00);
+        let mut node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        node.earned_amount = NEAR;
+        contract.nodes.insert(&node.node_id, &node);
 
-        set_context("mainer_a", 0 * NEAR, 5);
+        contract.claim_earnings(None);
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+
+        set_context("mainer_a", 0, 5);
         contract.remove_node();
+        assert!(contract.get_node("mainer_a".parse().unwrap()).is_none());
 
-        set_context("client_a", 0 * NEAR, 5);
-        contract.withdraw_balance();
+        set_promise_results(vec![PromiseResult::Failed]);
+        assert!(!contract.resolve_claim_earnings(node, None, U128(NEAR)));
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.staked_amount, 0);
+        assert_eq!(node.earned_amount, NEAR);
+        assert_eq!(node.state, NodeState::Exiting);
+        assert_eq!(contract.balance, 0);
     }
 
     #[test]
-    fn call_flow_end_active() {
+    #[should_panic(expected = "E002: Low balance")]
+    fn node_claim_earnings_panic_nothing_earned() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        contract.claim_earnings(None);
+    }
+
+    #[test]
+    fn node_remove_failed() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+        let usdc: AccountId = "usdc.token".parse().unwrap();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let mut node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        node.earned_amount = NEAR;
+        node.earned_tokens.insert(usdc.clone(), 1_000);
+        contract.nodes.insert(&node.node_id, &node);
+
+        contract.remove_node();
+        assert!(contract.get_node("mainer_a".parse().unwrap()).is_none());
+
+        // The NEAR payout went through, the token one didn't.
+        set_promise_results(vec![
+            PromiseResult::Successful(vec![]),
+            PromiseResult::Failed,
+        ]);
+        let removed = contract.resolve_remove_node(node, vec![(usdc.clone(), U128(1_000))]);
+        assert!(!removed);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.staked_amount, 0);
+        assert_eq!(node.earned_amount, 0);
+        assert_eq!(node.earned_tokens[&usdc], 1_000);
+        assert_eq!(node.state, NodeState::Exiting);
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E305: Node must be unused in calls and disputes")]
+    fn node_remove_panic_active_call() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
         let keypair: Keypair = prepare_keypair();
 
-        set_context("client_a", 1 * NEAR, 1);
+        set_context("client_a", NEAR, 1);
         contract.add_balance();
 
         set_context("mainer_a", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
 
-        let signature1 = keypair.sign(b"123:0:1");
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
         contract.create_call(
             "123".to_string(),
             "client_a".to_string(),
             1,
-            bs58::encode(signature1).into_string(),
+            signature,
+            None,
+            None,
         );
 
-        set_context("dtelecom", 0 * NEAR, 1);
-        contract.end_active_call("123".to_string(), 0);
-
-        let calls = contract.get_active_calls();
-        assert_eq!(calls.len(), 0);
+        // The stake lock is over, but the call is still going.
+        set_context("mainer_a", 0, 5);
+        contract.remove_node();
     }
 
     #[test]
-    fn call_recent_rotate() {
+    #[should_panic(expected = "E305: Node must be unused in calls and disputes")]
+    fn node_remove_panic_disputable_call() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
         let keypair: Keypair = prepare_keypair();
 
-        set_context("client_a", 1 * NEAR, 1);
+        set_context("dtelecom", 0, 1);
+        // 3 days, longer than the epoch lock
+        contract.set_dispute_window(U64(259_200_000_000_000));
+
+        set_context("client_a", NEAR, 1);
         contract.add_balance();
 
         set_context("mainer_a", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
 
-        let signature1 = keypair.sign(b"123:0:1");
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
         contract.create_call(
             "123".to_string(),
             "client_a".to_string(),
             1,
-            bs58::encode(signature1).into_string(),
-        );
-
-        let signature2 = keypair.sign(b"123:100:1");
-        contract.end_call(
-            "123".to_string(),
-            "client_a".to_string(),
-            100,
-            1,
-            bs58::encode(signature2).into_string(),
+            signature1,
+            None,
+            None,
         );
 
-        let calls0 = contract.get_recent_calls_0();
-        assert_eq!(calls0.len(), 0);
+        set_context("mainer_a", 0, 2);
+        let signature2 = sign(&keypair, CallAction::End, "123", 10, 2);
+        contract.end_call("123".to_string(), "client_a".to_string(), 10, 2, signature2);
 
-        let calls1 = contract.get_recent_calls_1();
-        assert_eq!(calls1.len(), 1);
+        // The epoch lock is over, but the call can be disputed until epoch 8.
+        set_context("mainer_a", 0, 5);
+        contract.remove_node();
+    }
 
-        set_context("mainer_a", 10 * NEAR, 2);
+    #[test]
+    #[should_panic(expected = "E417: Dispute window more than 2592000000000000 ns")]
+    fn config_panic_dispute_window_above_max() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        let signature3 = keypair.sign(b"1234:0:2");
-        contract.create_call(
-            "1234".to_string(),
-            "client_a".to_string(),
-            2,
-            bs58::encode(signature3).into_string(),
-        );
+        set_context("dtelecom", 0, 1);
+        contract.set_dispute_window(U64(MAX_DISPUTE_WINDOW + 1));
+    }
 
-        let signature4 = keypair.sign(b"1234:100:2");
-        contract.end_call(
-            "1234".to_string(),
-            "client_a".to_string(),
-            100,
-            2,
-            bs58::encode(signature4).into_string(),
-        );
+    #[test]
+    fn node_slash_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        let calls0_1 = contract.get_recent_calls_0();
-        assert_eq!(calls0_1.len(), 1);
+        let keypair: Keypair = prepare_keypair();
 
-        let calls1_1 = contract.get_recent_calls_1();
-        assert_eq!(calls1_1.len(), 1);
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
 
-        set_context("mainer_a", 10 * NEAR, 3);
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
 
-        let signature4 = keypair.sign(b"12345:0:3");
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
         contract.create_call(
-            "12345".to_string(),
+            "123".to_string(),
             "client_a".to_string(),
-            3,
-            bs58::encode(signature4).into_string(),
+            1,
+            signature1,
+            None,
+            None,
         );
 
-        let signature5 = keypair.sign(b"12345:100:3");
-        contract.end_call(
-            "12345".to_string(),
+        slash_through_dispute(&mut contract, "123", NEAR);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.state, NodeState::Underbonded);
+        assert_eq!(node.staked_amount, 9 * NEAR);
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 0);
+
+        set_context("mainer_a", NEAR, 1);
+        contract.top_up_stake();
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"node_stake_topped_up","data":{"node_id":"mainer_a","amount":"1000000000000000000000000","staked_amount":"10000000000000000000000000","state":"active"}}"#
+            ]
+        );
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 1);
+
+        let signature2 = sign(&keypair, CallAction::Create, "1234", 0, 1);
+        contract.create_call(
+            "1234".to_string(),
             "client_a".to_string(),
-            100,
-            3,
-            bs58::encode(signature5).into_string(),
+            1,
+            signature2,
+            None,
+            None,
         );
 
-        let calls0_2 = contract.get_recent_calls_0();
-        assert_eq!(calls0_2.len(), 1);
+        // A fine above the stake takes what is left and jails the node.
+        set_context("dtelecom", 0, 1);
+        contract.set_max_fine(U128(20 * NEAR));
+        slash_through_dispute(&mut contract, "1234", 20 * NEAR);
 
-        let calls1_2 = contract.get_recent_calls_1();
-        assert_eq!(calls1_2.len(), 1);
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.state, NodeState::Jailed);
+        assert_eq!(node.staked_amount, 0);
 
-        let call = &contract.get_recent_calls_1()[0];
-        assert_eq!(call.id, "12345");
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(
+            client.deposited_amount,
+            999_000_000_000_000_000_000_000 + 11 * NEAR
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn create_call_panic_no_client() {
+    #[should_panic(expected = "E304: Not enough staked")]
+    fn create_call_panic_underbonded() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
         let keypair: Keypair = prepare_keypair();
 
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
         set_context("mainer_a", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
 
-        let signature = keypair.sign(b"123:0:1");
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
         contract.create_call(
             "123".to_string(),
             "client_a".to_string(),
             1,
-            bs58::encode(signature).into_string(),
+            signature1,
+            None,
+            None,
+        );
+
+        slash_through_dispute(&mut contract, "123", NEAR);
+
+        set_context("mainer_a", 0, 1);
+        let signature2 = sign(&keypair, CallAction::Create, "1234", 0, 1);
+        contract.create_call(
+            "1234".to_string(),
+            "client_a".to_string(),
+            1,
+            signature2,
+            None,
+            None,
         );
     }
 
     #[test]
-    #[should_panic]
-    fn create_call_panic_no_node() {
+    #[should_panic(expected = "E309: Node is jailed")]
+    fn node_panic_top_up_jailed() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
         let keypair: Keypair = prepare_keypair();
 
-        set_context("client_a", 1 * NEAR, 1);
+        set_context("client_a", NEAR, 1);
         contract.add_balance();
 
         set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
 
-        let signature = keypair.sign(b"123:0:1");
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
         contract.create_call(
             "123".to_string(),
             "client_a".to_string(),
             1,
-            bs58::encode(signature).into_string(),
+            signature1,
+            None,
+            None,
         );
+
+        set_context("dtelecom", 0, 1);
+        contract.set_max_fine(U128(10 * NEAR));
+        slash_through_dispute(&mut contract, "123", 10 * NEAR);
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.top_up_stake();
     }
 
     #[test]
-    #[should_panic]
-    fn call_double_spend() {
+    fn node_update_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        contract.update_node("https://example.org/".to_string(), node_metadata("us-east"));
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.address, "https://example.org/");
+        assert_eq!(node.metadata.region, "us-east");
+        assert_eq!(node.staked_amount, 10 * NEAR);
+
+        // The old address is free again.
+        set_context("mainer_b", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        set_context("mainer_a", 0, 1);
+        contract.deactivate_node();
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 1);
+
+        contract.activate_node();
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 2);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"node_deactivated","data":{"node_id":"mainer_a"}}"#,
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"node_activated","data":{"node_id":"mainer_a"}}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn node_region_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node(
+            "https://a.example.com/".to_string(),
+            node_metadata("eu-west"),
+        );
+
+        set_context("mainer_b", 10 * NEAR, 1);
+        contract.add_node(
+            "https://b.example.com/".to_string(),
+            node_metadata("us-east"),
+        );
+
+        set_context("mainer_c", 10 * NEAR, 1);
+        contract.add_node(
+            "https://c.example.com/".to_string(),
+            node_metadata("eu-west"),
+        );
+
+        let nodes = contract.get_nodes_by_region("eu-west".to_string(), Some(0), Some(10));
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].node_id, "mainer_a".parse().unwrap());
+        assert_eq!(nodes[1].node_id, "mainer_c".parse().unwrap());
+
+        let nodes = contract.get_nodes_by_region("eu-west".to_string(), Some(1), Some(10));
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].node_id, "mainer_c".parse().unwrap());
+
+        contract.deactivate_node();
+        let nodes = contract.get_nodes_by_region("eu-west".to_string(), Some(0), Some(10));
+        assert_eq!(nodes.len(), 1);
+    }
+
+    #[test]
+    fn node_capacity_flow() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
         let keypair: Keypair = prepare_keypair();
 
-        set_context("client_a", 1 * NEAR, 1);
+        set_context("client_a", NEAR, 1);
         contract.add_balance();
 
+        let mut metadata = node_metadata("eu-west");
+        metadata.max_concurrent_calls = 1;
+
         set_context("mainer_a", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
+        contract.add_node("https://example.com/".to_string(), metadata);
+        assert_eq!(
+            contract.get_nodes_with_capacity(Some(0), Some(100)).len(),
+            1
+        );
 
-        let signature1 = keypair.sign(b"123:0:1");
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
         contract.create_call(
             "123".to_string(),
             "client_a".to_string(),
             1,
-            bs58::encode(signature1).into_string(),
+            signature1,
+            None,
+            None,
+        );
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.active_calls, 1);
+        assert_eq!(
+            contract.get_nodes_with_capacity(Some(0), Some(100)).len(),
+            0
         );
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 1);
 
-        let signature2 = keypair.sign(b"123:100:1");
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
         contract.end_call(
             "123".to_string(),
             "client_a".to_string(),
             100,
             1,
-            bs58::encode(signature2).into_string(),
+            signature2,
+        );
+        assert_eq!(
+            contract.get_nodes_with_capacity(Some(0), Some(100)).len(),
+            1
         );
-        assert_eq!(contract.balance, 485_000_000_000_000_000_000_00);
 
+        let signature3 = sign(&keypair, CallAction::Create, "1234", 0, 1);
         contract.create_call(
-            "123".to_string(),
+            "1234".to_string(),
             "client_a".to_string(),
             1,
-            bs58::encode(signature1).into_string(),
+            signature3,
+            None,
+            None,
         );
+
+        set_context("dtelecom", 0, 1);
+        contract.end_active_call("1234".to_string());
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.active_calls, 0);
     }
 
     #[test]
-    fn call_fine() {
+    #[should_panic(expected = "E308: Node is at max concurrent calls")]
+    fn create_call_panic_node_at_capacity() {
         let mut contract = Contract::init("dtelecom".parse().unwrap());
 
         let keypair: Keypair = prepare_keypair();
 
-        set_context("client_a", 1 * NEAR, 1);
+        set_context("client_a", NEAR, 1);
         contract.add_balance();
 
+        let mut metadata = node_metadata("eu-west");
+        metadata.max_concurrent_calls = 1;
+
         set_context("mainer_a", 10 * NEAR, 1);
-        contract.add_node("https://example.com/".to_string());
+        contract.add_node("https://example.com/".to_string(), metadata);
 
-        let signature1 = keypair.sign(b"123:0:1");
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
         contract.create_call(
             "123".to_string(),
             "client_a".to_string(),
             1,
-            bs58::encode(signature1).into_string(),
+            signature1,
+            None,
+            None,
         );
 
-        let signature2 = keypair.sign(b"123:100:1");
-        contract.end_call(
-            "123".to_string(),
+        let signature2 = sign(&keypair, CallAction::Create, "1234", 0, 1);
+        contract.create_call(
+            "1234".to_string(),
             "client_a".to_string(),
-            100,
             1,
-            bs58::encode(signature2).into_string(),
+            signature2,
+            None,
+            None,
         );
+    }
 
-        set_context("dtelecom", 0 * NEAR, 1);
-        contract.fine_recent_call("123".to_string(), 1_000_000_000_000_000_000_000_000);
+    #[test]
+    #[should_panic(expected = "E307: Invalid node metadata codecs")]
+    fn node_panic_invalid_metadata() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        let mut metadata = node_metadata("eu-west");
+        metadata.codecs.clear();
 
-        assert_eq!(node.staked_amount, 9_000_000_000_000_000_000_000_000);
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), metadata);
     }
 
-    fn prepare_keypair() -> Keypair {
-        let secret_key: &[u8] = b"833fe62409237b9d62ec77587520911e9a759cec1d19755b7da901b96dca3d42";
-        let public_key: &[u8] = b"ec172b93ad5e563bf4932c70e1245034c35467ef2efd4d64ebf819683467e2bf";
-        let sec_bytes: Vec<u8> = FromHex::from_hex(secret_key).unwrap();
-        let pub_bytes: Vec<u8> = FromHex::from_hex(public_key).unwrap();
+    #[test]
+    #[should_panic(expected = "E302: Address exists")]
+    fn node_update_panic_address_exists() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
 
-        let mut bytes: [u8; 64] = [0u8; 64];
-        bytes[..32].copy_from_slice(&sec_bytes[..]);
-        bytes[32..].copy_from_slice(&pub_bytes[..]);
-        Keypair::from_bytes(&bytes).unwrap()
-    }
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
 
-    fn set_context(predecessor: &str, amount: Balance, epoch: EpochHeight) {
+        set_context("mainer_b", 10 * NEAR, 1);
+        contract.add_node("https://example.org/".to_string(), node_metadata("eu-west"));
+
+        contract.update_node("https://example.com/".to_string(), node_metadata("eu-west"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_flow_panic() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        set_context("mainer_b", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_panic() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("mainer_a", NEAR, 1);
+        contract.remove_node();
+    }
+
+    #[test]
+    fn call_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let calls1 = contract.get_active_calls(Some(0), Some(100));
+        assert_eq!(calls1.len(), 1);
+
+        let call_started = &calls1[0];
+        assert_eq!(call_started.id, "123");
+        assert_eq!(call_started.client_id, "client_a".parse().unwrap());
+        assert_eq!(call_started.node_id, "mainer_a".parse().unwrap());
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature2,
+        );
+        let calls2 = contract.get_active_calls(Some(0), Some(100));
+        assert_eq!(calls2.len(), 0);
+
+        assert_eq!(contract.balance, 50_000_000_000_000_000_000_000);
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 899_000_000_000_000_000_000_000);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+
+        assert_eq!(node.earned_amount, 50_000_000_000_000_000_000_000);
+
+        set_context("mainer_a", 0, 5);
+        contract.remove_node();
+
+        set_context("client_a", 0, 5);
+        contract.withdraw_balance(None);
+    }
+
+    #[test]
+    fn call_flow_end_active() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        set_context("dtelecom", 0, 1);
+        contract.end_active_call("123".to_string());
+
+        let calls = contract.get_active_calls(Some(0), Some(100));
+        assert_eq!(calls.len(), 0);
+    }
+
+    #[test]
+    fn call_dispute_window() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature2,
+        );
+
+        let call = contract.get_recent_call("123".to_string()).unwrap();
+        assert_eq!(
+            call.dispute_expires_at,
+            EPOCH_DURATION + DEFAULT_DISPUTE_WINDOW
+        );
+
+        // Still open a whole epoch later, where the old rotation dropped it.
+        set_context("mainer_a", 0, 2);
+        let signature3 = sign(&keypair, CallAction::Create, "1234", 0, 2);
+        contract.create_call(
+            "1234".to_string(),
+            "client_a".to_string(),
+            2,
+            signature3,
+            None,
+            None,
+        );
+
+        let signature4 = sign(&keypair, CallAction::End, "1234", 100, 2);
+        contract.end_call(
+            "1234".to_string(),
+            "client_a".to_string(),
+            100,
+            2,
+            signature4,
+        );
+
+        set_context("client_a", 0, 2);
+        contract.open_dispute(
+            "123".to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+        assert_eq!(contract.prune_expired_calls(10), 0);
+        assert_eq!(contract.get_recent_calls(Some(0), Some(100)).len(), 2);
+
+        set_context("anyone", 0, 3);
+        assert_eq!(contract.prune_expired_calls(10), 1);
+
+        let calls = contract.get_recent_calls(Some(0), Some(100));
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "1234");
+    }
+
+    #[test]
+    #[should_panic(expected = "E407: Dispute window is over")]
+    fn dispute_panic_window_closed() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature2,
+        );
+
+        set_context("client_a", 0, 3);
+        contract.open_dispute(
+            "123".to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn create_call_panic_no_client() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn create_call_panic_no_node() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn call_double_spend() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1.clone(),
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature2,
+        );
+        assert_eq!(contract.balance, 50_000_000_000_000_000_000_000);
+
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn dispute_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature2,
+        );
+
+        set_context("client_a", 0, 1);
+        contract.open_dispute(
+            "123".to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(
+            client.deposited_amount,
+            899_000_000_000_000_000_000_000 - DEFAULT_DISPUTE_BOND
+        );
+
+        set_context("mainer_a", 0, 1);
+        contract.respond_dispute(
+            "123".to_string(),
+            "client_a".parse().unwrap(),
+            "response".to_string(),
+        );
+        let dispute = contract
+            .get_dispute("123".to_string(), "client_a".parse().unwrap())
+            .unwrap();
+        assert_eq!(dispute.response_hash, Some("response".to_string()));
+
+        // Held until the dispute is resolved, though the call is long pruned.
+        set_context("anyone", 0, 5);
+        contract.prune_expired_calls(10);
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.open_disputes, 1);
+
+        set_context("dtelecom", 0, 5);
+        contract.resolve_dispute(
+            "123".to_string(),
+            "client_a".parse().unwrap(),
+            true,
+            U128(NEAR),
+        );
+        assert!(contract.get_disputes(Some(0), Some(100)).is_empty());
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.staked_amount, 9_000_000_000_000_000_000_000_000);
+        assert_eq!(node.open_disputes, 0);
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 1_899_000_000_000_000_000_000_000);
+    }
+
+    #[test]
+    fn dispute_per_participant() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature2);
+
+        let signature_leave = sign(&keypair_b, CallAction::Leave, "123", 10, 1);
+        contract.leave_call(
+            "123".to_string(),
+            "client_b".to_string(),
+            10,
+            1,
+            signature_leave,
+        );
+
+        let signature3 = sign(&keypair, CallAction::End, "123", 10, 1);
+        contract.end_call("123".to_string(), "client_a".to_string(), 10, 1, signature3);
+
+        // Either participant's dispute leaves the other free to open its own.
+        for client in ["client_a", "client_b"] {
+            set_context(client, 0, 1);
+            contract.open_dispute(
+                "123".to_string(),
+                "evidence".to_string(),
+                U128(DEFAULT_DISPUTE_BOND),
+            );
+        }
+        assert_eq!(contract.get_disputes(None, None).len(), 2);
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.open_disputes, 2);
+
+        set_context("dtelecom", 0, 1);
+        contract.resolve_dispute(
+            "123".to_string(),
+            "client_b".parse().unwrap(),
+            false,
+            U128(0),
+        );
+        assert!(contract
+            .get_dispute("123".to_string(), "client_b".parse().unwrap())
+            .is_none());
+        assert!(contract
+            .get_dispute("123".to_string(), "client_a".parse().unwrap())
+            .is_some());
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.open_disputes, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "E411: Call already disputed by client")]
+    fn dispute_panic_exists() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 0, 1);
+        contract.end_call("123".to_string(), "client_a".to_string(), 0, 1, signature2);
+
+        set_context("client_a", 0, 1);
+        for _ in 0..2 {
+            contract.open_dispute(
+                "123".to_string(),
+                "evidence".to_string(),
+                U128(DEFAULT_DISPUTE_BOND),
+            );
+        }
+    }
+
+    #[test]
+    fn dispute_rejected() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 0, 1);
+        contract.end_call("123".to_string(), "client_a".to_string(), 0, 1, signature2);
+
+        set_context("client_a", 0, 1);
+        contract.open_dispute(
+            "123".to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+
+        set_context("dtelecom", 0, 1);
+        contract.resolve_dispute(
+            "123".to_string(),
+            "client_a".parse().unwrap(),
+            false,
+            U128(0),
+        );
+
+        assert_eq!(contract.balance, DEFAULT_DISPUTE_BOND);
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.staked_amount, 10 * NEAR);
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(
+            client.deposited_amount,
+            999_000_000_000_000_000_000_000 - DEFAULT_DISPUTE_BOND
+        );
+    }
+
+    #[test]
+    fn dispute_expired() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 0, 1);
+        contract.end_call("123".to_string(), "client_a".to_string(), 0, 1, signature2);
+
+        set_context("client_a", 0, 1);
+        contract.open_dispute(
+            "123".to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+        let dispute = contract
+            .get_dispute("123".to_string(), "client_a".parse().unwrap())
+            .unwrap();
+        assert_eq!(dispute.resolve_by, EPOCH_DURATION + DEFAULT_DISPUTE_TIMEOUT);
+
+        // No resolver acted within the timeout, 14 epochs.
+        set_context("anyone", 0, 15);
+        contract.expire_dispute("123".to_string(), "client_a".parse().unwrap());
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"dispute_expired","data":{"call_id":"123","client_id":"client_a","node_id":"mainer_a","bond":"10000000000000000000000"}}"#
+            ]
+        );
+        assert!(contract
+            .get_dispute("123".to_string(), "client_a".parse().unwrap())
+            .is_none());
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.open_disputes, 0);
+        assert_eq!(node.staked_amount, 10 * NEAR);
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 999_000_000_000_000_000_000_000);
+
+        set_context("mainer_a", 0, 15);
+        contract.remove_node();
+    }
+
+    #[test]
+    #[should_panic(expected = "E415: Dispute deadline not reached")]
+    fn dispute_panic_expire_before_timeout() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 0, 1);
+        contract.end_call("123".to_string(), "client_a".to_string(), 0, 1, signature2);
+
+        set_context("client_a", 0, 1);
+        contract.open_dispute(
+            "123".to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+
+        set_context("anyone", 0, 14);
+        contract.expire_dispute("123".to_string(), "client_a".parse().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "E409: Fine more than 1000000000000000000000000 yoctoNEAR")]
+    fn dispute_panic_fine_above_max() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 0, 1);
+        contract.end_call("123".to_string(), "client_a".to_string(), 0, 1, signature2);
+
+        set_context("client_a", 0, 1);
+        contract.open_dispute(
+            "123".to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+
+        set_context("dtelecom", 0, 1);
+        contract.resolve_dispute(
+            "123".to_string(),
+            "client_a".parse().unwrap(),
+            true,
+            U128(2 * NEAR),
+        );
+    }
+
+    #[test]
+    fn config_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        set_context("dtelecom", 0, 1);
+        contract.set_minute_price(U128(2 * DEFAULT_MINUTE_PRICE));
+        contract.set_stake_amount(U128(20 * NEAR));
+        assert_eq!(contract.get_config().minute_price, 2 * DEFAULT_MINUTE_PRICE);
+        assert_eq!(contract.get_config().stake_amount, 20 * NEAR);
+
+        // Staked under the old amount, so still listed and able to serve calls.
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 1);
+
+        set_context("mainer_a", 0, 1);
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature2,
+        );
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 799_000_000_000_000_000_000_000);
+
+        set_context("mainer_b", 20 * NEAR, 1);
+        contract.add_node(
+            "https://example2.com/".to_string(),
+            node_metadata("eu-west"),
+        );
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 2);
+    }
+
+    #[test]
+    fn revenue_split_conserves_funds() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("dtelecom", 0, 1);
+        // Odd amounts, so every split has a remainder.
+        contract.set_minute_price(U128(7));
+        contract.set_revenue_split(3_333, 1_000, Some("fund.dtelecom".parse().unwrap()));
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let total = total_funds(&contract);
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 50, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            Some(50),
+        );
+        assert_eq!(total_funds(&contract), total);
+
+        let signature2 = sign(&keypair, CallAction::Checkpoint, "123", 13, 1);
+        contract.checkpoint_call("123".to_string(), 13, 1, signature2);
+        assert_eq!(total_funds(&contract), total);
+
+        let signature3 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature3);
+
+        let signature4 = sign(&keypair_b, CallAction::Leave, "123", 11, 1);
+        contract.leave_call("123".to_string(), "client_b".to_string(), 11, 1, signature4);
+        assert_eq!(total_funds(&contract), total);
+
+        let signature5 = sign(&keypair, CallAction::End, "123", 37, 1);
+        contract.end_call("123".to_string(), "client_a".to_string(), 37, 1, signature5);
+        assert_eq!(total_funds(&contract), total);
+
+        // Charged 91, 77 and 168, rounded down for the node and the fund.
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.earned_amount, 30 + 25 + 55);
+        assert_eq!(contract.fund_balance, 9 + 7 + 16);
+        assert_eq!(contract.balance, 52 + 45 + 97);
+        assert_eq!(contract.get_total_earned(), U128(336));
+
+        set_context("client_a", 0, 1);
+        contract.withdraw_fund(None);
+        assert_eq!(contract.fund_balance, 0);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"fund_withdraw","data":{"fund_id":"fund.dtelecom","amount":"32"}}"#
+            ]
+        );
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        assert!(!contract.resolve_withdraw_fund(None, U128(32)));
+        assert_eq!(total_funds(&contract), total);
+    }
+
+    #[test]
+    #[should_panic(expected = "E006: Shares exceed 10000 basis points")]
+    fn revenue_split_panic_above_whole() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("dtelecom", 0, 1);
+        contract.set_revenue_split(8_000, 3_000, Some("fund.dtelecom".parse().unwrap()));
+    }
+
+    #[test]
+    fn role_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let monitor: AccountId = "monitor.dtelecom".parse().unwrap();
+
+        set_context("dtelecom", 0, 1);
+        contract.grant_role("admin.dtelecom".parse().unwrap(), Role::Admin);
+
+        set_context("admin.dtelecom", 0, 1);
+        contract.grant_role(monitor.clone(), Role::FineOperator);
+        contract.grant_role(monitor.clone(), Role::FineOperator);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"role_granted","data":{"account_id":"monitor.dtelecom","role":"fine_operator"}}"#
+            ]
+        );
+        contract.set_minute_price(U128(2 * DEFAULT_MINUTE_PRICE));
+
+        let roles = contract.get_roles(Some(0), Some(100));
+        assert_eq!(roles.len(), 2);
+        assert_eq!(roles[1], (monitor.clone(), vec![Role::FineOperator]));
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        set_context("monitor.dtelecom", 0, 1);
+        contract.end_active_call("123".to_string());
+        assert_eq!(contract.get_active_calls(Some(0), Some(100)).len(), 0);
+
+        set_context("admin.dtelecom", 0, 1);
+        contract.revoke_role(monitor.clone(), Role::FineOperator);
+        assert_eq!(contract.get_roles(Some(0), Some(100)).len(), 1);
+        assert!(!contract.has_role(&monitor, Role::FineOperator));
+    }
+
+    #[test]
+    #[should_panic(expected = "E008: Missing role Treasurer")]
+    fn role_panic_fine_operator_withdraw() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("dtelecom", 0, 1);
+        contract.grant_role("monitor.dtelecom".parse().unwrap(), Role::FineOperator);
+        contract.balance = NEAR;
+
+        set_context("monitor.dtelecom", 0, 1);
+        contract.withdraw(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E001: Method is private")]
+    fn role_panic_admin_grants_admin() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("dtelecom", 0, 1);
+        contract.grant_role("admin.dtelecom".parse().unwrap(), Role::Admin);
+
+        set_context("admin.dtelecom", 0, 1);
+        contract.grant_role("other.dtelecom".parse().unwrap(), Role::Admin);
+    }
+
+    #[test]
+    fn ownership_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("dtelecom", 0, 1);
+        contract.propose_owner("typo.dtelecom".parse().unwrap());
+        contract.propose_owner("dao.dtelecom".parse().unwrap());
+        assert_eq!(
+            contract.get_proposed_owner(),
+            Some("dao.dtelecom".parse().unwrap())
+        );
+        assert_eq!(contract.get_owner(), "dtelecom".parse().unwrap());
+
+        set_context("dao.dtelecom", 0, 1);
+        contract.accept_ownership();
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"ownership_transferred","data":{"previous_owner_id":"dtelecom","owner_id":"dao.dtelecom"}}"#
+            ]
+        );
+        assert_eq!(contract.get_owner(), "dao.dtelecom".parse().unwrap());
+        assert_eq!(contract.get_proposed_owner(), None);
+
+        contract.set_minute_price(U128(2 * DEFAULT_MINUTE_PRICE));
+        assert!(!contract.has_role(&"dtelecom".parse().unwrap(), Role::Admin));
+    }
+
+    #[test]
+    #[should_panic(expected = "E009: Not the proposed owner")]
+    fn ownership_panic_not_proposed() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("dtelecom", 0, 1);
+        contract.propose_owner("typo.dtelecom".parse().unwrap());
+
+        set_context("dao.dtelecom", 0, 1);
+        contract.accept_ownership();
+    }
+
+    #[test]
+    fn pause_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("dtelecom", 0, 1);
+        contract.grant_role("pauser.dtelecom".parse().unwrap(), Role::Pauser);
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("pauser.dtelecom", 0, 1);
+        contract.pause(Subsystem::Deposits);
+        contract.pause(Subsystem::Withdrawals);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"subsystem_paused","data":{"subsystem":"deposits","account_id":"pauser.dtelecom"}}"#,
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"subsystem_paused","data":{"subsystem":"withdrawals","account_id":"pauser.dtelecom"}}"#
+            ]
+        );
+        assert!(contract.is_paused(Subsystem::Deposits));
+        assert!(contract.is_paused(Subsystem::Withdrawals));
+        assert!(!contract.is_paused(Subsystem::CallCreation));
+
+        contract.unpause(Subsystem::Deposits);
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        // Withdrawals resume on their own, 3 days or 6 epochs later.
+        set_context("client_a", 0, 7);
+        assert!(!contract.is_paused(Subsystem::Withdrawals));
+        contract.withdraw_balance(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E010: CallCreation is paused")]
+    fn pause_panic_call_creation() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        set_context("dtelecom", 0, 1);
+        contract.pause(Subsystem::CallCreation);
+
+        set_context("mainer_a", 0, 1);
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E011: Withdrawals were paused too recently")]
+    fn pause_panic_withdrawals_again() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("dtelecom", 0, 1);
+        contract.pause(Subsystem::Withdrawals);
+
+        set_context("dtelecom", 0, 7);
+        contract.pause(Subsystem::Withdrawals);
+    }
+
+    #[test]
+    #[should_panic(expected = "E008: Missing role Admin")]
+    fn config_panic_not_owner() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("mainer_a", 0, 1);
+        contract.set_stake_amount(U128(NEAR));
+    }
+
+    #[test]
+    #[should_panic(expected = "E306: Node is deactivated")]
+    fn create_call_panic_inactive_node() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+        contract.deactivate_node();
+
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E101: Malformed signature")]
+    fn create_call_panic_malformed_signature() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            "0OIl".to_string(),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E103: Signature mismatch")]
+    fn create_call_panic_other_contract() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        // Signed for another deployment of the contract.
+        let signature = sign_message(
+            &keypair,
+            &SignedMessage {
+                version: SIGNED_MESSAGE_VERSION,
+                network: DEFAULT_NETWORK.to_string(),
+                contract_id: "staging.dtelecom".parse().unwrap(),
+                node_id: "mainer_a".parse().unwrap(),
+                action: CallAction::Create,
+                call_id: "123".to_string(),
+                token_id: None,
+                minutes: 0,
+                epoch: 1,
+            },
+        );
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E103: Signature mismatch")]
+    fn create_call_panic_other_network() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        // Same contract account on another network.
+        let signature = sign_message(
+            &keypair,
+            &SignedMessage {
+                version: SIGNED_MESSAGE_VERSION,
+                network: "testnet".to_string(),
+                contract_id: CONTRACT_ID.parse().unwrap(),
+                node_id: "mainer_a".parse().unwrap(),
+                action: CallAction::Create,
+                call_id: "123".to_string(),
+                token_id: None,
+                minutes: 0,
+                epoch: 1,
+            },
+        );
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E103: Signature mismatch")]
+    fn create_call_panic_other_node() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_b", 10 * NEAR, 1);
+        contract.add_node("https://example.org/".to_string(), node_metadata("eu-west"));
+
+        // Approved for mainer_a only.
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E103: Signature mismatch")]
+    fn end_call_panic_create_signature() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature.clone(),
+            None,
+            None,
+        );
+
+        contract.end_call("123".to_string(), "client_a".to_string(), 0, 1, signature);
+    }
+
+    #[test]
+    fn call_flow_network() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        set_context("dtelecom", 0, 1);
+        contract.set_network("testnet".to_string());
+
+        set_context("mainer_a", 0, 1);
+        let signature = sign_message(
+            &keypair,
+            &SignedMessage {
+                version: SIGNED_MESSAGE_VERSION,
+                network: "testnet".to_string(),
+                contract_id: CONTRACT_ID.parse().unwrap(),
+                node_id: "mainer_a".parse().unwrap(),
+                action: CallAction::Create,
+                call_id: "123".to_string(),
+                token_id: None,
+                minutes: 0,
+                epoch: 1,
+            },
+        );
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+
+        assert_eq!(contract.get_active_calls(Some(0), Some(100)).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: Malformed account id")]
+    fn end_call_panic_malformed_account() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "Client A".to_string(),
+            100,
+            1,
+            signature2,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E104: Wrong epoch 1")]
+    fn create_call_panic_future_epoch() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 5);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            5,
+            signature,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn call_events() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"client_deposit","data":{"client_id":"client_a","amount":"999000000000000000000000","deposited_amount":"999000000000000000000000"}}"#
+            ]
+        );
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        set_context("mainer_a", 0, 1);
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"call_created","data":{"call_id":"123","client_id":"client_a","node_id":"mainer_a","escrow":"120000000000000000000000"}}"#
+            ]
+        );
+
+        set_context("mainer_a", 0, 1);
+        let signature2 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature2,
+        );
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"call_ended","data":{"call_id":"123","client_id":"client_a","node_id":"mainer_a","minutes":"100","spent":"100000000000000000000000","node_earned":"50000000000000000000000","protocol_earned":"50000000000000000000000","fund_earned":"0","refunded":"20000000000000000000000"}}"#
+            ]
+        );
+
+        set_context("client_a", 0, 1);
+        contract.open_dispute(
+            "123".to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+        set_context("dtelecom", 0, 1);
+        contract.resolve_dispute(
+            "123".to_string(),
+            "client_a".parse().unwrap(),
+            true,
+            U128(NEAR),
+        );
+        contract.withdraw(None);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"node_slashed","data":{"node_id":"mainer_a","amount":"1000000000000000000000000","staked_amount":"9000000000000000000000000","state":"underbonded"}}"#,
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"dispute_resolved","data":{"call_id":"123","client_id":"client_a","node_id":"mainer_a","upheld":true,"fine":"1000000000000000000000000","bond":"10000000000000000000000"}}"#,
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"owner_withdraw","data":{"owner_id":"dtelecom","amount":"50000000000000000000000"}}"#
+            ]
+        );
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        assert!(!contract.resolve_withdraw(None, U128(50_000_000_000_000_000_000_000)));
+        assert_eq!(contract.balance, 50_000_000_000_000_000_000_000);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"owner_withdraw_restored","data":{"amount":"50000000000000000000000"}}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn call_checkpoint_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::Checkpoint, "123", 40, 1);
+        contract.checkpoint_call("123".to_string(), 40, 1, signature2);
+
+        let call = contract.get_active_call("123".to_string()).unwrap();
+        assert_eq!(call.minutes, 40);
+        assert_eq!(call.earned, 40 * DEFAULT_MINUTE_PRICE);
+
+        let call = contract.get_active_call("123".to_string()).unwrap();
+        assert_eq!(call.escrow, 80 * DEFAULT_MINUTE_PRICE);
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 879_000_000_000_000_000_000_000);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.earned_amount, 20 * DEFAULT_MINUTE_PRICE);
+
+        let signature3 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature3,
+        );
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 899_000_000_000_000_000_000_000);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.earned_amount, 50_000_000_000_000_000_000_000);
+        assert_eq!(contract.balance, 50_000_000_000_000_000_000_000);
+        assert_eq!(contract.get_total_minutes(), U128(100));
+
+        let call = contract.get_recent_call("123".to_string()).unwrap();
+        assert_eq!(call.minutes, 100);
+        assert_eq!(call.earned, 100 * DEFAULT_MINUTE_PRICE);
+    }
+
+    #[test]
+    #[should_panic(expected = "E404: Minutes below last checkpoint")]
+    fn call_checkpoint_panic_rewind() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::Checkpoint, "123", 40, 1);
+        contract.checkpoint_call("123".to_string(), 40, 1, signature2);
+
+        let signature3 = sign(&keypair, CallAction::End, "123", 30, 1);
+        contract.end_call("123".to_string(), "client_a".to_string(), 30, 1, signature3);
+    }
+
+    #[test]
+    fn call_expire_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair, CallAction::Checkpoint, "123", 40, 1);
+        contract.checkpoint_call("123".to_string(), 40, 1, signature2);
+
+        let call = contract.get_active_call("123".to_string()).unwrap();
+        assert_eq!(call.deadline, EPOCH_DURATION + DEFAULT_MAX_CALL_DURATION);
+
+        // The node went silent; the client hands over its latest approval.
+        set_context("anyone", 0, 3);
+        contract.expire_call(
+            "123".to_string(),
+            Some(SignedCheckpoint {
+                minutes: 60,
+                epoch: 1,
+                sign: sign(&keypair, CallAction::Checkpoint, "123", 60, 1),
+            }),
+        );
+
+        assert_eq!(contract.get_active_calls(Some(0), Some(100)).len(), 0);
+        let call = contract.get_recent_call("123".to_string()).unwrap();
+        assert_eq!(call.minutes, 60);
+        assert_eq!(call.earned, 60 * DEFAULT_MINUTE_PRICE);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.expired_calls, 1);
+        assert_eq!(node.active_calls, 0);
+        assert_eq!(node.earned_amount, 30 * DEFAULT_MINUTE_PRICE);
+
+        set_context("client_a", 0, 3);
+        contract.withdraw_balance(None);
+    }
+
+    #[test]
+    fn call_expire_removed_node() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            None,
+        );
+
+        // Left while hosting the call, as remove_node allowed before it
+        // checked active calls.
+        contract.nodes.remove(&"mainer_a".parse().unwrap());
+
+        set_context("anyone", 0, 3);
+        contract.expire_call("123".to_string(), None);
+
+        assert_eq!(contract.get_active_calls(None, None).len(), 0);
+        let call = contract.get_recent_call("123".to_string()).unwrap();
+        assert_eq!(call.escrow, 0);
+        assert!(contract.get_node("mainer_a".parse().unwrap()).is_none());
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, NEAR - DEFAULT_STORAGE_COST);
+
+        set_context("client_a", 0, 3);
+        contract.withdraw_balance(None);
+    }
+
+    #[test]
+    fn call_expire_heartbeat() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("dtelecom", 0, 1);
+        contract.set_heartbeat_timeout(U64(EPOCH_DURATION));
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        set_context("anyone", 0, 2);
+        contract.expire_call("123".to_string(), None);
+
+        let call = contract.get_recent_call("123".to_string()).unwrap();
+        assert_eq!(call.minutes, 0);
+        assert_eq!(call.earned, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E408: Call deadline not reached")]
+    fn call_expire_panic_before_deadline() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        set_context("anyone", 0, 2);
+        contract.expire_call("123".to_string(), None);
+    }
+
+    #[test]
+    fn call_index_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature2);
+
+        let signature_leave = sign(&keypair_b, CallAction::Leave, "123", 100, 1);
+        contract.leave_call(
+            "123".to_string(),
+            "client_b".to_string(),
+            100,
+            1,
+            signature_leave,
+        );
+
+        let signature3 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature3,
+        );
+
+        set_context("mainer_a", 0, 2);
+        let signature4 = sign(&keypair, CallAction::Create, "1234", 0, 2);
+        contract.create_call(
+            "1234".to_string(),
+            "client_a".to_string(),
+            2,
+            signature4,
+            None,
+            None,
+        );
+
+        let client_a: AccountId = "client_a".parse().unwrap();
+        let client_b: AccountId = "client_b".parse().unwrap();
+        let mainer_a: AccountId = "mainer_a".parse().unwrap();
+
+        let calls = contract.get_client_calls(client_a.clone(), Some(0), Some(10));
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "123");
+        assert_eq!(calls[1].id, "1234");
+        assert_eq!(
+            contract
+                .get_client_calls(client_b.clone(), Some(0), Some(10))
+                .len(),
+            1
+        );
+
+        let calls = contract.get_node_calls(mainer_a.clone(), Some(1), Some(10));
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "1234");
+
+        // Only the call still running holds deposits.
+        assert!(contract.is_client_in_call(&client_a));
+        assert!(!contract.is_client_in_call(&client_b));
+
+        // Once its dispute window is over the ended call leaves the indexes.
+        set_context("anyone", 0, 4);
+        contract.prune_expired_calls(10);
+
+        assert_eq!(
+            contract.get_client_calls(client_a, Some(0), Some(10)).len(),
+            1
+        );
+        assert_eq!(
+            contract.get_client_calls(client_b, Some(0), Some(10)).len(),
+            0
+        );
+        assert_eq!(
+            contract.get_node_calls(mainer_a, Some(0), Some(10)).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn call_participants_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature2);
+
+        let call = contract.get_active_call("123".to_string()).unwrap();
+        assert_eq!(call.participants.len(), 1);
+        assert!(call.is_billing(&"client_b".parse().unwrap()));
+
+        let signature3 = sign(&keypair_b, CallAction::Leave, "123", 30, 1);
+        contract.leave_call("123".to_string(), "client_b".to_string(), 30, 1, signature3);
+
+        let call = contract.get_active_call("123".to_string()).unwrap();
+        assert!(!call.is_billing(&"client_b".parse().unwrap()));
+        assert_eq!(call.participants[0].minutes, 30);
+        assert_eq!(call.participants[0].earned, 30 * DEFAULT_MINUTE_PRICE);
+
+        let signature4 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature4,
+        );
+
+        let client_a = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client_a.deposited_amount, 899_000_000_000_000_000_000_000);
+
+        let client_b = contract.get_client("client_b".parse().unwrap()).unwrap();
+        assert_eq!(client_b.deposited_amount, 969_000_000_000_000_000_000_000);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.earned_amount, 65 * DEFAULT_MINUTE_PRICE);
+        assert_eq!(contract.balance, 65 * DEFAULT_MINUTE_PRICE);
+    }
+
+    #[test]
+    #[should_panic(expected = "E416: Participants must leave first")]
+    fn call_end_panic_participants_active() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature2);
+
+        let signature3 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature3,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E203: Deposit must be unused")]
+    fn call_participant_panic_withdraw() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature2);
+
+        set_context_with_key("client_b", 0, 1, keypair_b.public.as_bytes());
+        contract.withdraw_balance(None);
+    }
+
+    #[test]
+    fn balance_withdraw_amount_during_call() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("dtelecom", 0, 1);
+        // 100 minutes, so joining a call reserves 0.1 NEAR
+        contract.set_max_call_duration(U64(6_000_000_000_000));
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature2);
+
+        // The creator's share is already in escrow.
+        set_context("client_a", 0, 1);
+        contract.withdraw_balance_amount(U128(879_000_000_000_000_000_000_000), None);
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 0);
+
+        set_context_with_key("client_b", 0, 1, keypair_b.public.as_bytes());
+        contract.withdraw_balance_amount(U128(899_000_000_000_000_000_000_000), None);
+        let client = contract.get_client("client_b".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 100_000_000_000_000_000_000_000);
+
+        // Billed minutes no longer need to be reserved.
+        set_context("mainer_a", 0, 1);
+        let signature3 = sign(&keypair_b, CallAction::Leave, "123", 40, 1);
+        contract.leave_call("123".to_string(), "client_b".to_string(), 40, 1, signature3);
+
+        set_context_with_key("client_b", 0, 1, keypair_b.public.as_bytes());
+        contract.withdraw_balance_amount(U128(60_000_000_000_000_000_000_000), None);
+        let client = contract.get_client("client_b".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 0);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "E205: Withdraw at most 899000000000000000000000 not reserved by calls"
+    )]
+    fn balance_withdraw_amount_panic_reserved() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("dtelecom", 0, 1);
+        contract.set_max_call_duration(U64(6_000_000_000_000));
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        let signature2 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature2);
+
+        set_context_with_key("client_b", 0, 1, keypair_b.public.as_bytes());
+        contract.withdraw_balance_amount(U128(900_000_000_000_000_000_000_000), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E206: Amount must be positive")]
+    fn balance_withdraw_amount_panic_zero() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        contract.withdraw_balance_amount(U128(0), None);
+    }
+
+    #[test]
+    fn call_escrow_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 500, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            Some(500),
+        );
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 499_000_000_000_000_000_000_000);
+
+        // Without a signed maximum the default is escrowed.
+        let signature2 = sign(&keypair, CallAction::Create, "1234", 0, 1);
+        contract.create_call(
+            "1234".to_string(),
+            "client_a".to_string(),
+            1,
+            signature2,
+            None,
+            None,
+        );
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 379_000_000_000_000_000_000_000);
+
+        // Minutes past the escrow go unpaid, the node should have ended the call.
+        let signature3 = sign(&keypair, CallAction::End, "1234", 200, 1);
+        contract.end_call(
+            "1234".to_string(),
+            "client_a".to_string(),
+            200,
+            1,
+            signature3,
+        );
+        let call = contract.get_recent_call("1234".to_string()).unwrap();
+        assert_eq!(call.earned, 120 * DEFAULT_MINUTE_PRICE);
+        assert_eq!(call.escrow, 0);
+
+        let signature4 = sign(&keypair, CallAction::End, "123", 100, 1);
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature4,
+        );
+        let call = contract.get_recent_call("123".to_string()).unwrap();
+        assert_eq!(call.earned, 100 * DEFAULT_MINUTE_PRICE);
+        assert_eq!(call.escrow, 0);
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.deposited_amount, 779_000_000_000_000_000_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E414: Max minutes must be positive")]
+    fn create_call_panic_zero_max_minutes() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            Some(0),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E204: Not enough client balance")]
+    fn create_call_panic_escrow_above_balance() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature = sign(&keypair, CallAction::Create, "123", 1000, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            Some(1000),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E204: Not enough client balance")]
+    fn create_call_panic_escrow_overflow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let max_minutes = u128::MAX / 1000;
+        let signature = sign(&keypair, CallAction::Create, "123", max_minutes, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature,
+            None,
+            Some(max_minutes),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E204: Not enough client balance")]
+    fn create_call_panic_balance_reserved() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign(&keypair, CallAction::Create, "123", 0, 1);
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            None,
+            None,
+        );
+
+        // A day of minutes, more than the whole deposit, is now reserved.
+        let signature2 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature2);
+
+        let signature3 = sign(&keypair_b, CallAction::Create, "124", 0, 1);
+        contract.create_call(
+            "124".to_string(),
+            "client_b".to_string(),
+            1,
+            signature3,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E204: Not enough client balance")]
+    fn call_join_panic_balance_reserved() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let keypair_b: Keypair = prepare_keypair_b();
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context_with_key("client_b", NEAR, 1, keypair_b.public.as_bytes());
+        contract.add_balance();
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        for id in ["123", "124"] {
+            let signature = sign(&keypair, CallAction::Create, id, 0, 1);
+            contract.create_call(
+                id.to_string(),
+                "client_a".to_string(),
+                1,
+                signature,
+                None,
+                None,
+            );
+        }
+
+        let signature1 = sign(&keypair_b, CallAction::Join, "123", 0, 1);
+        contract.join_call("123".to_string(), "client_b".to_string(), 1, signature1);
+
+        let signature2 = sign(&keypair_b, CallAction::Join, "124", 0, 1);
+        contract.join_call("124".to_string(), "client_b".to_string(), 1, signature2);
+    }
+
+    #[test]
+    fn token_flow() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        let keypair: Keypair = prepare_keypair();
+        let usdc: AccountId = "usdc.token".parse().unwrap();
+
+        set_context("dtelecom", 0, 1);
+        contract.add_token(usdc.clone(), U128(1_000));
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("usdc.token", 0, 1);
+        contract.ft_on_transfer("client_a".parse().unwrap(), U128(1_000_000), String::new());
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.balance(Some(&usdc)), 1_000_000);
+
+        set_context("mainer_a", 10 * NEAR, 1);
+        contract.add_node("https://example.com/".to_string(), node_metadata("eu-west"));
+
+        let signature1 = sign_message(
+            &keypair,
+            &SignedMessage {
+                version: SIGNED_MESSAGE_VERSION,
+                network: DEFAULT_NETWORK.to_string(),
+                contract_id: CONTRACT_ID.parse().unwrap(),
+                node_id: "mainer_a".parse().unwrap(),
+                action: CallAction::Create,
+                call_id: "123".to_string(),
+                token_id: Some(usdc.clone()),
+                minutes: 0,
+                epoch: 1,
+            },
+        );
+        contract.create_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            1,
+            signature1,
+            Some(usdc.clone()),
+            None,
+        );
+
+        let signature2 = sign_message(
+            &keypair,
+            &SignedMessage {
+                version: SIGNED_MESSAGE_VERSION,
+                network: DEFAULT_NETWORK.to_string(),
+                contract_id: CONTRACT_ID.parse().unwrap(),
+                node_id: "mainer_a".parse().unwrap(),
+                action: CallAction::End,
+                call_id: "123".to_string(),
+                token_id: Some(usdc.clone()),
+                minutes: 100,
+                epoch: 1,
+            },
+        );
+        contract.end_call(
+            "123".to_string(),
+            "client_a".to_string(),
+            100,
+            1,
+            signature2,
+        );
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.balance(Some(&usdc)), 900_000);
+        assert_eq!(client.deposited_amount, 999_000_000_000_000_000_000_000);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.earned_tokens[&usdc], 50_000);
+        assert_eq!(node.earned_amount, 0);
+        assert_eq!(contract.get_token(usdc.clone()).unwrap().balance, 50_000);
+        assert_eq!(contract.balance, 0);
+        assert_eq!(contract.get_total_minutes(), U128(100));
+        assert_eq!(contract.get_total_earned(), U128(0));
+
+        set_context("client_a", 0, 1);
+        contract.withdraw_balance(Some(usdc.clone()));
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(client.balance(Some(&usdc)), 0);
+
+        set_context("dtelecom", 0, 1);
+        contract.withdraw(Some(usdc.clone()));
+        assert_eq!(contract.get_token(usdc).unwrap().balance, 0);
+
+        set_context("mainer_a", 0, 5);
+        contract.remove_node();
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"dtelecom","version":"1.0.0","event":"node_removed","data":{"node_id":"mainer_a","staked_amount":"10000000000000000000000000","earned_amount":"0","earned_tokens":{"usdc.token":"50000"}}}"#
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E500: Token not whitelisted")]
+    fn token_panic_not_whitelisted() {
+        let mut contract = Contract::init("dtelecom".parse().unwrap());
+
+        set_context("client_a", NEAR, 1);
+        contract.add_balance();
+
+        set_context("fake.token", 0, 1);
+        contract.ft_on_transfer("client_a".parse().unwrap(), U128(1_000_000), String::new());
+    }
+
+    #[test]
+    fn migrate_from_v1() {
+        set_context("dtelecom", 0, 1);
+
+        let mut old = ContractV1 {
+            clients: UnorderedMap::new(b"c"),
+            nodes: UnorderedMap::new(b"n"),
+            active_calls: UnorderedMap::new(b"a"),
+            recent_calls_0: UnorderedMap::new(b"f"),
+            recent_calls_1: UnorderedMap::new(b"s"),
+            balance: NEAR,
+            owner: "dtelecom".parse().unwrap(),
+            total_earned: 2 * NEAR,
+            total_conferences: 3,
+            total_minutes: 100,
+            prev_storage_key: 1,
+        };
+        old.recent_calls_1.insert(
+            &"122".to_string(),
+            &CallV1 {
+                id: "122".to_string(),
+                client_id: "client_a".parse().unwrap(),
+                node_id: "mainer_a".parse().unwrap(),
+                minutes: 10,
+                created_at: 0,
+                ended_at: EPOCH_DURATION,
+                earned: 10 * DEFAULT_MINUTE_PRICE,
+            },
+        );
+        old.clients.insert(
+            &"client_a".parse().unwrap(),
+            &ClientV1 {
+                deposited_amount: NEAR,
+                pk: env::signer_account_pk(),
+            },
+        );
+        old.nodes.insert(
+            &"mainer_a".parse().unwrap(),
+            &NodeV1 {
+                address: "https://example.com/".to_string(),
+                staked_amount: 9 * NEAR,
+                earned_amount: NEAR,
+                active: true,
+                unstaked_available_epoch_height: 4,
+                pk: env::signer_account_pk(),
+                node_id: "mainer_a".parse().unwrap(),
+            },
+        );
+        old.active_calls.insert(
+            &"123".to_string(),
+            &CallV1 {
+                id: "123".to_string(),
+                client_id: "client_a".parse().unwrap(),
+                node_id: "mainer_a".parse().unwrap(),
+                minutes: 0,
+                created_at: 0,
+                ended_at: 0,
+                earned: 0,
+            },
+        );
+        env::state_write(&old);
+
+        let contract = Contract::migrate();
+
+        assert_eq!(contract.owner, "dtelecom".parse().unwrap());
+        assert_eq!(contract.balance, NEAR);
+        assert_eq!(contract.get_total_conferences(), U128(3));
+        assert_eq!(contract.get_config().stake_amount, DEFAULT_STAKE_AMOUNT);
+
+        let client = contract.get_client("client_a".parse().unwrap()).unwrap();
+        assert_eq!(
+            client.deposited_amount,
+            NEAR - DEFAULT_MAX_MINUTES * DEFAULT_MINUTE_PRICE
+        );
+        let call = contract.get_active_call("123".to_string()).unwrap();
+        assert_eq!(call.escrow, DEFAULT_MAX_MINUTES * DEFAULT_MINUTE_PRICE);
+
+        let node = contract.get_node("mainer_a".parse().unwrap()).unwrap();
+        assert_eq!(node.address, "https://example.com/");
+        assert_eq!(node.staked_amount, 9 * NEAR);
+        assert_eq!(node.required_stake, DEFAULT_STAKE_AMOUNT);
+        assert_eq!(node.earned_amount, NEAR);
+        assert_eq!(node.unstaked_available_epoch_height, 4);
+        assert_eq!(node.active_calls, 1);
+        // Fined below the original stake before the upgrade.
+        assert_eq!(node.state, NodeState::Underbonded);
+
+        let calls = contract.get_client_calls("client_a".parse().unwrap(), Some(0), Some(10));
+        assert_eq!(calls.len(), 2);
+        assert!(contract.is_client_in_call(&"client_a".parse().unwrap()));
+
+        let call = contract.get_active_call("123".to_string()).unwrap();
+        assert_eq!(call.client_id, "client_a".parse().unwrap());
+
+        let call = contract.get_recent_call("122".to_string()).unwrap();
+        assert_eq!(call.minutes, 10);
+        assert_eq!(
+            call.dispute_expires_at,
+            EPOCH_DURATION + DEFAULT_DISPUTE_WINDOW
+        );
+
+        // Migrating the current layout again keeps it as is.
+        env::state_write(&contract);
+        let contract = Contract::migrate();
+        assert_eq!(contract.get_nodes(Some(0), Some(100)).len(), 0);
+        assert_eq!(contract.get_total_nodes(), U64(1));
+    }
+
+    /// Every NEAR amount the contract accounts for, outside of stakes.
+    fn total_funds(contract: &Contract) -> Balance {
+        let clients: Balance = contract
+            .clients
+            .values()
+            .map(|client| client.deposited_amount)
+            .sum();
+        let escrows: Balance = contract
+            .active_calls
+            .values()
+            .filter(|call| call.token_id.is_none())
+            .map(|call| call.escrow)
+            .sum();
+        let earned: Balance = contract.nodes.values().map(|node| node.earned_amount).sum();
+
+        clients + escrows + earned + contract.balance + contract.fund_balance
+    }
+
+    fn prepare_keypair() -> Keypair {
+        let secret_key: &[u8] = b"833fe62409237b9d62ec77587520911e9a759cec1d19755b7da901b96dca3d42";
+        let public_key: &[u8] = b"ec172b93ad5e563bf4932c70e1245034c35467ef2efd4d64ebf819683467e2bf";
+        let sec_bytes: Vec<u8> = FromHex::from_hex(secret_key).unwrap();
+        let pub_bytes: Vec<u8> = FromHex::from_hex(public_key).unwrap();
+
+        let mut bytes: [u8; 64] = [0u8; 64];
+        bytes[..32].copy_from_slice(&sec_bytes[..]);
+        bytes[32..].copy_from_slice(&pub_bytes[..]);
+        Keypair::from_bytes(&bytes).unwrap()
+    }
+
+    fn prepare_keypair_b() -> Keypair {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[7u8; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn node_metadata(region: &str) -> NodeMetadata {
+        NodeMetadata {
+            region: region.to_string(),
+            max_participants: 100,
+            max_concurrent_calls: 10,
+            version: "1.0.0".to_string(),
+            codecs: vec!["opus".to_string(), "vp8".to_string()],
+            simulcast: true,
+            svc: false,
+        }
+    }
+
+    /// Force-ends `call_id` and fines its node `fine` over a dispute
+    /// `client_a` opens and the owner upholds.
+    fn slash_through_dispute(contract: &mut Contract, call_id: &str, fine: Balance) {
+        set_context("dtelecom", 0, 1);
+        contract.end_active_call(call_id.to_string());
+
+        set_context("client_a", 0, 1);
+        contract.open_dispute(
+            call_id.to_string(),
+            "evidence".to_string(),
+            U128(DEFAULT_DISPUTE_BOND),
+        );
+
+        set_context("dtelecom", 0, 1);
+        contract.resolve_dispute(
+            call_id.to_string(),
+            "client_a".parse().unwrap(),
+            true,
+            U128(fine),
+        );
+    }
+
+    fn sign(
+        keypair: &Keypair,
+        action: CallAction,
+        id: &str,
+        minutes: u128,
+        epoch: EpochHeight,
+    ) -> String {
+        sign_message(
+            keypair,
+            &SignedMessage {
+                version: SIGNED_MESSAGE_VERSION,
+                network: DEFAULT_NETWORK.to_string(),
+                contract_id: CONTRACT_ID.parse().unwrap(),
+                node_id: "mainer_a".parse().unwrap(),
+                action,
+                call_id: id.to_string(),
+                token_id: None,
+                minutes,
+                epoch,
+            },
+        )
+    }
+
+    fn sign_message(keypair: &Keypair, message: &SignedMessage) -> String {
+        bs58::encode(keypair.sign(&message.to_bytes())).into_string()
+    }
+
+    /// Context of a `resolve_*` callback that sees `results` for its transfers.
+    fn set_promise_results(results: Vec<PromiseResult>) {
         let mut builder = VMContextBuilder::new();
+        builder.current_account_id(CONTRACT_ID.parse().unwrap());
+        builder.predecessor_account_id(CONTRACT_ID.parse().unwrap());
+
+        testing_env!(
+            builder.build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            results,
+        );
+    }
 
+    fn set_context(predecessor: &str, amount: Balance, epoch: EpochHeight) {
         let pk: &[u8] = b"ec172b93ad5e563bf4932c70e1245034c35467ef2efd4d64ebf819683467e2bf";
         let pub_bytes: Vec<u8> = FromHex::from_hex(pk).unwrap();
 
-        let spk = "ed25519:".to_owned() + &bs58::encode(pub_bytes).into_string();
+        set_context_with_key(predecessor, amount, epoch, &pub_bytes);
+    }
+
+    fn set_context_with_key(predecessor: &str, amount: Balance, epoch: EpochHeight, pk: &[u8]) {
+        let mut builder = VMContextBuilder::new();
+
+        let spk = "ed25519:".to_owned() + &bs58::encode(pk).into_string();
 
         let public_key: PublicKey = spk.parse().unwrap();
 
-        builder.current_account_id(predecessor.parse().unwrap());
+        builder.current_account_id(CONTRACT_ID.parse().unwrap());
         builder.predecessor_account_id(predecessor.parse().unwrap());
         builder.attached_deposit(amount);
         builder.signer_account_id(predecessor.parse().unwrap());
         builder.signer_account_pk(public_key);
         builder.epoch_height(epoch);
+        builder.block_timestamp(epoch * EPOCH_DURATION);
 
         testing_env!(builder.build());
     }
@@ -0,0 +1,245 @@
+use crate::*;
+use std::collections::HashMap;
+
+/// Holds the layout version of the contract state. States written before
+/// versioning was introduced don't have it and are read as `V1`.
+const STATE_VERSION_KEY: &[u8] = b"VERSION";
+
+/// Layout written by this build. Once it is deployed the layout is frozen:
+/// copy the structs into a new `VersionedContract` variant before changing
+/// `Contract`, `Client`, `Node` or `Call`, and bump this.
+pub const STATE_VERSION: u8 = 2;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ClientV1 {
+    pub deposited_amount: Balance,
+    pub pk: PublicKey,
+}
+
+impl From<ClientV1> for Client {
+    fn from(client: ClientV1) -> Self {
+        Self {
+            deposited_amount: client.deposited_amount,
+            pk: client.pk,
+            token_balances: HashMap::new(),
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct NodeV1 {
+    pub address: String,
+    pub staked_amount: Balance,
+    pub earned_amount: Balance,
+    pub active: bool,
+    pub unstaked_available_epoch_height: EpochHeight,
+    pub pk: PublicKey,
+    pub node_id: AccountId,
+}
+
+impl From<NodeV1> for Node {
+    fn from(node: NodeV1) -> Self {
+        Self {
+            address: node.address,
+            // Left for the operator to publish with `update_node`, without
+            // limiting calls in the meantime.
+            metadata: NodeMetadata {
+                max_concurrent_calls: u32::MAX,
+                ..NodeMetadata::default()
+            },
+            // Counted from the active calls once they are migrated.
+            active_calls: 0,
+            expired_calls: 0,
+            open_disputes: 0,
+            // Raised from the recent calls once they are migrated.
+            disputable_until: 0,
+            staked_amount: node.staked_amount,
+            // Every node up to V1 staked the original fixed amount.
+            required_stake: DEFAULT_STAKE_AMOUNT,
+            earned_amount: node.earned_amount,
+            earned_tokens: HashMap::new(),
+            state: if !node.active {
+                NodeState::Exiting
+            } else if node.staked_amount == 0 {
+                NodeState::Jailed
+            } else if node.staked_amount < DEFAULT_STAKE_AMOUNT {
+                NodeState::Underbonded
+            } else {
+                NodeState::Active
+            },
+            unstaked_available_epoch_height: node.unstaked_available_epoch_height,
+            pk: node.pk,
+            node_id: node.node_id,
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct CallV1 {
+    pub id: String,
+    pub client_id: AccountId,
+    pub node_id: AccountId,
+    pub minutes: u128,
+    pub created_at: u64,
+    pub ended_at: u64,
+    pub earned: u128,
+}
+
+impl From<CallV1> for Call {
+    fn from(call: CallV1) -> Self {
+        Self {
+            id: call.id,
+            client_id: call.client_id,
+            node_id: call.node_id,
+            minutes: call.minutes,
+            created_at: call.created_at,
+            ended_at: call.ended_at,
+            earned: call.earned,
+            token_id: None,
+            // Escrowed from the client once the clients are migrated.
+            escrow: 0,
+            deadline: call.created_at + DEFAULT_MAX_CALL_DURATION,
+            dispute_expires_at: 0,
+            participants: Vec::new(),
+        }
+    }
+}
+
+/// Layout deployed before the state was versioned.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ContractV1 {
+    pub clients: UnorderedMap<AccountId, ClientV1>,
+    pub nodes: UnorderedMap<AccountId, NodeV1>,
+    pub active_calls: UnorderedMap<String, CallV1>,
+    pub recent_calls_0: UnorderedMap<String, CallV1>,
+    pub recent_calls_1: UnorderedMap<String, CallV1>,
+    pub balance: Balance,
+    pub owner: AccountId,
+    pub total_earned: Balance,
+    pub total_conferences: u128,
+    pub total_minutes: u128,
+    pub prev_storage_key: EpochHeight,
+}
+
+// Only built once, to be converted right away.
+#[allow(clippy::large_enum_variant)]
+pub enum VersionedContract {
+    V1(ContractV1),
+    V2(Contract),
+}
+
+impl VersionedContract {
+    fn read() -> Self {
+        let version = env::storage_read(STATE_VERSION_KEY).map_or(1, |v| v[0]);
+        match version {
+            1 => Self::V1(read_state()),
+            STATE_VERSION => Self::V2(read_state()),
+            _ => ContractError::UnknownStateVersion.panic(),
+        }
+    }
+}
+
+fn read_state<T: BorshDeserialize>() -> T {
+    env::state_read().unwrap_or_else(|| ContractError::StateNotFound.panic())
+}
+
+impl From<VersionedContract> for Contract {
+    fn from(state: VersionedContract) -> Self {
+        match state {
+            VersionedContract::V1(mut old) => {
+                let mut contract = Self {
+                    clients: migrate_map(old.clients),
+                    nodes: migrate_map(old.nodes),
+                    active_calls: migrate_map(old.active_calls),
+                    recent_calls: UnorderedMap::new(b"r"),
+                    recent_calls_by_expiry: TreeMap::new(b"e"),
+                    balance: old.balance,
+                    fund_balance: 0,
+                    owner: old.owner,
+                    proposed_owner: None,
+                    total_earned: old.total_earned,
+                    total_conferences: old.total_conferences,
+                    total_minutes: old.total_minutes,
+                    config: Config::default(),
+                    tokens: UnorderedMap::new(b"t"),
+                    client_calls: LookupMap::new(b"l"),
+                    node_calls: LookupMap::new(b"m"),
+                    client_active_calls: LookupMap::new(b"k"),
+                    roles: UnorderedMap::new(b"o"),
+                    paused: PauseState::default(),
+                    disputes: UnorderedMap::new(b"d"),
+                };
+
+                // Calls up to V1 were billed straight from the deposit, so
+                // they escrow what they would have been capped at, up to
+                // the default maximum.
+                let escrow = contract.config.default_max_minutes * contract.config.minute_price;
+                for (id, mut call) in contract.active_calls.to_vec() {
+                    if let Some(mut node) = contract.nodes.get(&call.node_id) {
+                        node.active_calls += 1;
+                        contract.nodes.insert(&call.node_id, &node);
+                    }
+                    if let Some(mut client) = contract.clients.get(&call.client_id) {
+                        call.escrow = escrow.min(client.deposited_amount);
+                        client.deposited_amount -= call.escrow;
+                        contract.clients.insert(&call.client_id, &client);
+                        contract.active_calls.insert(&id, &call);
+                    }
+                    contract.index_call(&call);
+                    contract.index_active_call(&call);
+                }
+
+                // The two epoch slots become one map, each call fineable for
+                // a full window from when it ended.
+                for recent_calls in [&mut old.recent_calls_0, &mut old.recent_calls_1] {
+                    for call in recent_calls.values() {
+                        let mut call: Call = call.into();
+                        call.dispute_expires_at = call.ended_at + contract.config.dispute_window;
+                        if let Some(mut node) = contract.nodes.get(&call.node_id) {
+                            node.disputable_until =
+                                node.disputable_until.max(call.dispute_expires_at);
+                            contract.nodes.insert(&call.node_id, &node);
+                        }
+                        contract.insert_recent_call(&call);
+                        contract.index_call(&call);
+                    }
+                    recent_calls.clear();
+                }
+
+                contract
+            }
+            VersionedContract::V2(contract) => contract,
+        }
+    }
+}
+
+/// Rewrites every value of `map` in the new layout, keeping its storage prefix.
+fn migrate_map<K, Old, New>(mut map: UnorderedMap<K, Old>) -> UnorderedMap<K, New>
+where
+    K: BorshSerialize + BorshDeserialize,
+    Old: BorshSerialize + BorshDeserialize + Into<New>,
+    New: BorshSerialize + BorshDeserialize,
+{
+    for (key, value) in map.to_vec() {
+        let value: New = value.into();
+        map.insert_raw(&key.try_to_vec().unwrap(), &value.try_to_vec().unwrap());
+    }
+    UnorderedMap::try_from_slice(&map.try_to_vec().unwrap()).unwrap()
+}
+
+pub(crate) fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &[STATE_VERSION]);
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Upgrades the stored state to the layout of this build. Records whose
+    /// layout changed are rewritten in this one call, so it has to fit in gas.
+    #[init(ignore_state)]
+    #[private]
+    pub fn migrate() -> Self {
+        let contract: Contract = VersionedContract::read().into();
+        write_state_version();
+        contract
+    }
+}
@@ -1,81 +1,555 @@
 use crate::*;
+use near_sdk::is_promise_success;
+use std::collections::HashMap;
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+/// Longest region, version or codec name a node can publish.
+const MAX_METADATA_FIELD_LEN: usize = 32;
+const MAX_CODECS: usize = 16;
+
+/// What the load balancer needs to pick a node, published by its operator.
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NodeMetadata {
+    /// Where the node runs, such as `eu-west`. Matched exactly by `get_nodes_by_region`.
+    pub region: String,
+    /// Participants the node can serve at once, across all its calls.
+    pub max_participants: u32,
+    /// Calls the node can host at once, enforced by `create_call`.
+    pub max_concurrent_calls: u32,
+    /// SFU software version.
+    pub version: String,
+    /// Codec names such as `opus` or `vp8`.
+    pub codecs: Vec<String>,
+    pub simulcast: bool,
+    pub svc: bool,
+}
+
+impl NodeMetadata {
+    fn validate(&self) {
+        let valid_name = |name: &String| !name.is_empty() && name.len() <= MAX_METADATA_FIELD_LEN;
+
+        let invalid_field = if !valid_name(&self.region) {
+            Some("region")
+        } else if self.max_participants == 0 {
+            Some("max_participants")
+        } else if self.max_concurrent_calls == 0 {
+            Some("max_concurrent_calls")
+        } else if !valid_name(&self.version) {
+            Some("version")
+        } else if self.codecs.is_empty()
+            || self.codecs.len() > MAX_CODECS
+            || !self.codecs.iter().all(valid_name)
+        {
+            Some("codecs")
+        } else {
+            None
+        };
+
+        if let Some(field) = invalid_field {
+            ContractError::InvalidNodeMetadata { field }.panic()
+        }
+    }
+}
+
+/// Where a node is in its lifecycle, and so whether it may create calls.
+/// Calls it already hosts can be settled in every state.
+#[derive(
+    BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum NodeState {
+    /// Fully staked and listed. The only state that may create calls.
+    Active,
+    /// Slashed below its required stake. Creates no calls and is unlisted
+    /// until `top_up_stake` restores the stake.
+    Underbonded,
+    /// Slashed down to nothing, which deregisters it for good. Creates no
+    /// calls and can only be removed.
+    Jailed,
+    /// Deactivated by its operator to leave. Creates no calls until
+    /// `activate_node`.
+    Exiting,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Node {
     pub address: String,
+    pub metadata: NodeMetadata,
+    /// Calls the node hosts right now.
+    pub active_calls: u32,
+    /// Calls the node abandoned until they were expired, see `expire_call`.
+    pub expired_calls: u32,
+    /// Disputes against the node waiting for a resolver. It can't be
+    /// removed until there are none.
+    pub open_disputes: u32,
+    /// When the last call the node served stops being disputable, see
+    /// `Config::dispute_window`. It can't be removed before.
+    pub disputable_until: u64,
     pub staked_amount: Balance,
+    /// Stake the node had to lock when it registered.
+    pub required_stake: Balance,
     pub earned_amount: Balance,
-    pub active: bool,
+    /// Earnings in whitelisted tokens, paid out with `ft_transfer`.
+    pub earned_tokens: HashMap<AccountId, Balance>,
+    pub state: NodeState,
     pub unstaked_available_epoch_height: EpochHeight,
     pub pk: PublicKey,
     pub node_id: AccountId,
 }
 
+impl Node {
+    /// Whether the node still holds a full stake. Nodes registered under an
+    /// older, higher `Config::stake_amount` are held to the amount they staked.
+    pub fn is_staked(&self, stake_amount: Balance) -> bool {
+        self.staked_amount >= self.required_stake.min(stake_amount)
+    }
+
+    /// Aborts unless the node is `Active`, with the reason it isn't.
+    pub fn assert_may_create_calls(&self) {
+        match self.state {
+            NodeState::Active => {}
+            NodeState::Underbonded => ContractError::NotEnoughStaked.panic(),
+            NodeState::Jailed => ContractError::NodeJailed.panic(),
+            NodeState::Exiting => ContractError::NodeInactive.panic(),
+        }
+    }
+
+    /// Takes up to `amount` from the stake, never more than is left, and
+    /// returns what was taken. An `Active` node left below its required
+    /// stake becomes `Underbonded`, and any node left with nothing `Jailed`.
+    pub fn slash(&mut self, amount: Balance, stake_amount: Balance) -> Balance {
+        let slashed = amount.min(self.staked_amount);
+        self.staked_amount -= slashed;
+
+        if self.staked_amount == 0 {
+            self.state = NodeState::Jailed;
+        } else if self.state == NodeState::Active && !self.is_staked(stake_amount) {
+            self.state = NodeState::Underbonded;
+        }
+
+        slashed
+    }
+
+    pub fn has_capacity(&self) -> bool {
+        self.active_calls < self.metadata.max_concurrent_calls
+    }
+
+    /// Earnings in `token_id`, or in NEAR for `None`.
+    pub fn earned_mut(&mut self, token_id: Option<&AccountId>) -> &mut Balance {
+        match token_id {
+            Some(token_id) => self.earned_tokens.entry(token_id.clone()).or_insert(0),
+            None => &mut self.earned_amount,
+        }
+    }
+}
+
 #[near_bindgen]
 impl Contract {
     #[payable]
-    pub fn add_node(&mut self, address: String) {
+    pub fn add_node(&mut self, address: String, metadata: NodeMetadata) {
+        self.assert_not_paused(Subsystem::NodeRegistration);
+
         let previous = self.nodes.get(&env::predecessor_account_id());
-        assert!(previous.is_none() == true, "Node exist");
+        ensure(previous.is_none(), ContractError::NodeExists);
 
-        let exist_nodes: Vec<Node> = self
-            .nodes
-            .values()
-            .filter(|n| n.address == address)
-            .collect();
-        assert!(exist_nodes.len() == 0, "Adress exist");
+        self.assert_address_free(&address);
+        metadata.validate();
 
         let deposit_amount: Balance = env::attached_deposit();
-        assert!(
-            deposit_amount == STAKE_AMOUNT,
-            "Deposit exact {} yoctoNEAR",
-            STAKE_AMOUNT
+        ensure(
+            deposit_amount == self.config.stake_amount,
+            ContractError::WrongStake {
+                expected: self.config.stake_amount,
+            },
         );
 
         let node = Node {
-            address: address,
+            address,
+            metadata,
+            active_calls: 0,
+            expired_calls: 0,
+            open_disputes: 0,
+            disputable_until: 0,
             staked_amount: deposit_amount,
+            required_stake: deposit_amount,
             earned_amount: 0,
-            active: true,
+            earned_tokens: HashMap::new(),
+            state: NodeState::Active,
             unstaked_available_epoch_height: 0,
             pk: env::signer_account_pk(),
             node_id: env::predecessor_account_id(),
         };
 
         self.nodes.insert(&env::predecessor_account_id(), &node);
+
+        Event::NodeRegistered {
+            node_id: node.node_id,
+            address: node.address,
+            staked_amount: U128(node.staked_amount),
+        }
+        .emit();
     }
 
     pub fn remove_node(&mut self) -> Promise {
-        let node = expect_token_found(self.nodes.get(&env::predecessor_account_id()));
+        self.assert_not_paused(Subsystem::Withdrawals);
 
-        require!(
-            node.unstaked_available_epoch_height < env::epoch_height(),
-            "Node must be unused in recent calls"
+        let node = self
+            .nodes
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+
+        ensure(
+            node.unstaked_available_epoch_height < env::epoch_height()
+                && node.active_calls == 0
+                && node.open_disputes == 0
+                && node.disputable_until <= env::block_timestamp(),
+            ContractError::NodeInUse,
         );
 
         let to_withdraw = node.staked_amount + node.earned_amount;
 
         self.nodes.remove(&env::predecessor_account_id());
 
-        Promise::new(env::predecessor_account_id()).transfer(to_withdraw)
+        // Paid in this order, which the callback relies on to match results.
+        let earned_tokens: Vec<(AccountId, U128)> = node
+            .earned_tokens
+            .iter()
+            .filter(|(_, amount)| **amount > 0)
+            .map(|(token_id, amount)| (token_id.clone(), U128(*amount)))
+            .collect();
+
+        Event::NodeRemoved {
+            node_id: node.node_id.clone(),
+            staked_amount: U128(node.staked_amount),
+            earned_amount: U128(node.earned_amount),
+            earned_tokens: earned_tokens.iter().cloned().collect(),
+        }
+        .emit();
+
+        let payouts = earned_tokens.iter().fold(
+            Promise::new(env::predecessor_account_id()).transfer(to_withdraw),
+            |promise, (token_id, amount)| {
+                promise.and(ft_transfer(
+                    token_id.clone(),
+                    env::predecessor_account_id(),
+                    amount.0,
+                ))
+            },
+        );
+
+        payouts.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .resolve_remove_node(node, earned_tokens),
+        )
     }
 
+    /// Brings back a removed node holding whatever `remove_node` failed to
+    /// pay: the first result is the NEAR transfer, then one per entry of
+    /// `earned_tokens`. Returns whether every payout went through.
+    #[private]
+    pub fn resolve_remove_node(
+        &mut self,
+        node: Node,
+        earned_tokens: Vec<(AccountId, U128)>,
+    ) -> bool {
+        let near_paid = promise_succeeded(0);
+        let unpaid_tokens: HashMap<AccountId, Balance> = earned_tokens
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !promise_succeeded(*index as u64 + 1))
+            .map(|(_, (token_id, amount))| (token_id.clone(), amount.0))
+            .collect();
+
+        if near_paid && unpaid_tokens.is_empty() {
+            Event::NodeRemovalPaid {
+                node_id: node.node_id,
+                staked_amount: U128(node.staked_amount),
+                earned_amount: U128(node.earned_amount),
+                earned_tokens: earned_tokens.into_iter().collect(),
+            }
+            .emit();
+            return true;
+        }
+
+        let (staked_amount, earned_amount) = if near_paid {
+            (0, 0)
+        } else {
+            (node.staked_amount, node.earned_amount)
+        };
+
+        let restored = match self.nodes.get(&node.node_id) {
+            // Registered again in the meantime, so the stake can't come back as stake.
+            Some(mut current) => {
+                current.earned_amount += staked_amount + earned_amount;
+                for (token_id, amount) in unpaid_tokens.iter() {
+                    *current.earned_mut(Some(token_id)) += amount;
+                }
+                current
+            }
+            // It asked to leave, so it comes back exiting.
+            None => Node {
+                staked_amount,
+                earned_amount,
+                earned_tokens: unpaid_tokens.clone(),
+                state: NodeState::Exiting,
+                ..node
+            },
+        };
+        self.nodes.insert(&restored.node_id, &restored);
+
+        Event::NodeRestored {
+            node_id: restored.node_id,
+            staked_amount: U128(staked_amount),
+            earned_amount: U128(earned_amount),
+            earned_tokens: unpaid_tokens
+                .into_iter()
+                .map(|(token_id, amount)| (token_id, U128(amount)))
+                .collect(),
+        }
+        .emit();
+
+        false
+    }
+
+    /// Pays out the earnings in `token_id`, or in NEAR when omitted, and
+    /// keeps the node registered with its stake.
+    pub fn claim_earnings(&mut self, token_id: Option<AccountId>) -> Promise {
+        self.assert_not_paused(Subsystem::Withdrawals);
+
+        let mut node = self
+            .nodes
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+
+        let amount = std::mem::take(node.earned_mut(token_id.as_ref()));
+        ensure(amount > 0, ContractError::LowBalance);
+
+        self.nodes.insert(&env::predecessor_account_id(), &node);
+
+        Event::NodeEarningsClaimed {
+            node_id: node.node_id.clone(),
+            amount: U128(amount),
+            token_id: token_id.clone(),
+        }
+        .emit();
+
+        let transfer = match token_id.clone() {
+            Some(token_id) => ft_transfer(token_id, node.node_id.clone(), amount),
+            None => Promise::new(node.node_id.clone()).transfer(amount),
+        };
+
+        transfer.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .resolve_claim_earnings(node, token_id, U128(amount)),
+        )
+    }
+
+    /// Credits a failed `claim_earnings` payout back to the node, `node`
+    /// being its record when it claimed. Returns whether the payout went
+    /// through.
+    #[private]
+    pub fn resolve_claim_earnings(
+        &mut self,
+        node: Node,
+        token_id: Option<AccountId>,
+        amount: U128,
+    ) -> bool {
+        if is_promise_success() {
+            Event::NodeEarningsPaid {
+                node_id: node.node_id,
+                amount,
+                token_id,
+            }
+            .emit();
+            return true;
+        }
+
+        let mut restored = match self.nodes.get(&node.node_id) {
+            Some(current) => current,
+            // Removed in the meantime with its stake paid out, so it comes
+            // back exiting with only the earnings, as in `resolve_remove_node`.
+            None => Node {
+                staked_amount: 0,
+                earned_amount: 0,
+                earned_tokens: HashMap::new(),
+                state: NodeState::Exiting,
+                ..node
+            },
+        };
+        *restored.earned_mut(token_id.as_ref()) += amount.0;
+        self.nodes.insert(&restored.node_id, &restored);
+
+        Event::NodeEarningsRestored {
+            node_id: restored.node_id,
+            amount,
+            token_id,
+        }
+        .emit();
+
+        false
+    }
+
+    /// Changes where and how the node is reached, keeping its stake and calls.
+    pub fn update_node(&mut self, address: String, metadata: NodeMetadata) {
+        let mut node = self
+            .nodes
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+
+        if node.address != address {
+            self.assert_address_free(&address);
+        }
+        metadata.validate();
+
+        node.address = address;
+        node.metadata = metadata;
+        self.nodes.insert(&env::predecessor_account_id(), &node);
+
+        Event::NodeUpdated {
+            node_id: node.node_id,
+            address: node.address,
+        }
+        .emit();
+    }
+
+    /// Brings an `Exiting` node back, `Underbonded` if it was slashed meanwhile.
+    pub fn activate_node(&mut self) {
+        let mut node = self
+            .nodes
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+        ensure(node.state != NodeState::Jailed, ContractError::NodeJailed);
+        if node.state == NodeState::Exiting {
+            node.state = if node.is_staked(self.config.stake_amount) {
+                NodeState::Active
+            } else {
+                NodeState::Underbonded
+            };
+        }
+        self.nodes.insert(&env::predecessor_account_id(), &node);
+
+        Event::NodeActivated {
+            node_id: node.node_id,
+        }
+        .emit();
+    }
+
+    /// Stops new calls so the node can leave once its calls are over.
     pub fn deactivate_node(&mut self) {
-        let mut node = expect_token_found(self.nodes.get(&env::predecessor_account_id()));
-        node.active = false;
+        let mut node = self
+            .nodes
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+        ensure(node.state != NodeState::Jailed, ContractError::NodeJailed);
+        node.state = NodeState::Exiting;
         self.nodes.insert(&env::predecessor_account_id(), &node);
+
+        Event::NodeDeactivated {
+            node_id: node.node_id,
+        }
+        .emit();
     }
 
-    pub fn get_nodes(&self) -> Vec<Node> {
-        self.nodes
-            .values()
-            .filter(|n| n.staked_amount == STAKE_AMOUNT)
-            .filter(|n| n.active == true)
+    /// `Active` nodes, paged over the matches in storage order.
+    pub fn get_nodes(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Node> {
+        let (from_index, limit) = page(from_index, limit);
+        self.listed_nodes()
+            .skip(from_index as usize)
+            .take(limit as usize)
             .collect()
     }
 
+    /// Listed nodes, as in `get_nodes`, that can take another call.
+    pub fn get_nodes_with_capacity(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Node> {
+        let (from_index, limit) = page(from_index, limit);
+        self.listed_nodes()
+            .filter(|n| n.has_capacity())
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Listed nodes, as in `get_nodes`, in `region`. Paged over the matches.
+    pub fn get_nodes_by_region(
+        &self,
+        region: String,
+        from: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Node> {
+        let (from, limit) = page(from, limit);
+        self.listed_nodes()
+            .filter(|n| n.metadata.region == region)
+            .skip(from as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Adds the attached deposit to the caller's stake. An `Underbonded`
+    /// node whose stake is whole again becomes `Active`.
+    #[payable]
+    pub fn top_up_stake(&mut self) {
+        let mut node = self
+            .nodes
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NodeNotFound.panic());
+
+        ensure(node.state != NodeState::Jailed, ContractError::NodeJailed);
+        let amount = env::attached_deposit();
+        ensure(amount > 0, ContractError::LowDeposit);
+
+        node.staked_amount += amount;
+        if node.state == NodeState::Underbonded && node.is_staked(self.config.stake_amount) {
+            node.state = NodeState::Active;
+        }
+        self.nodes.insert(&env::predecessor_account_id(), &node);
+
+        Event::NodeStakeToppedUp {
+            node_id: node.node_id,
+            amount: U128(amount),
+            staked_amount: U128(node.staked_amount),
+            state: node.state,
+        }
+        .emit();
+    }
+
     pub fn get_node(&self, account: AccountId) -> Option<Node> {
         self.nodes.get(&account)
     }
 }
+
+impl Contract {
+    fn listed_nodes(&self) -> impl Iterator<Item = Node> + '_ {
+        self.nodes.values().filter(|n| n.state == NodeState::Active)
+    }
+
+    /// Slashes `node` by up to `fine`, see `Node::slash`, and returns what
+    /// was taken. The caller saves the node.
+    pub(crate) fn slash_node(&self, node: &mut Node, fine: Balance) -> Balance {
+        let slashed = node.slash(fine, self.config.stake_amount);
+        if slashed > 0 {
+            Event::NodeSlashed {
+                node_id: node.node_id.clone(),
+                amount: U128(slashed),
+                staked_amount: U128(node.staked_amount),
+                state: node.state,
+            }
+            .emit();
+        }
+        slashed
+    }
+
+    fn assert_address_free(&self, address: &str) {
+        ensure(
+            self.nodes.values().all(|n| n.address != address),
+            ContractError::AddressExists,
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use crate::*;
+
+// 3 days
+pub const MAX_WITHDRAWALS_PAUSE: u64 = 259_200_000_000_000;
+
+/// A part of the contract that can be paused during an incident.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    /// `add_balance` and `ft_on_transfer`.
+    Deposits,
+    /// `add_node`.
+    NodeRegistration,
+    /// `create_call` and `join_call`.
+    CallCreation,
+    /// `checkpoint_call`, `end_call`, `leave_call` and `expire_call`.
+    CallSettlement,
+    /// Every transfer out: `withdraw_balance`, `withdraw_balance_amount`,
+    /// `claim_earnings`, `remove_node`, `withdraw` and `withdraw_fund`.
+    Withdrawals,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseState {
+    pub deposits: bool,
+    pub node_registration: bool,
+    pub call_creation: bool,
+    pub call_settlement: bool,
+    /// Withdrawals are paused before this, for at most
+    /// `MAX_WITHDRAWALS_PAUSE`. Zero if they never were.
+    pub withdrawals_until: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Makes the methods of `subsystem` fail until `unpause`. Withdrawals
+    /// resume by themselves after `MAX_WITHDRAWALS_PAUSE`, and can only be
+    /// paused again as long after that, so funds are never stuck for good.
+    pub fn pause(&mut self, subsystem: Subsystem) {
+        self.assert_role(Role::Pauser);
+
+        match subsystem {
+            Subsystem::Deposits => self.paused.deposits = true,
+            Subsystem::NodeRegistration => self.paused.node_registration = true,
+            Subsystem::CallCreation => self.paused.call_creation = true,
+            Subsystem::CallSettlement => self.paused.call_settlement = true,
+            Subsystem::Withdrawals => {
+                let until = self.paused.withdrawals_until;
+                ensure(
+                    until == 0 || env::block_timestamp() >= until + MAX_WITHDRAWALS_PAUSE,
+                    ContractError::PauseCooldown,
+                );
+                self.paused.withdrawals_until = env::block_timestamp() + MAX_WITHDRAWALS_PAUSE;
+            }
+        }
+
+        Event::SubsystemPaused {
+            subsystem,
+            account_id: env::predecessor_account_id(),
+        }
+        .emit();
+    }
+
+    pub fn unpause(&mut self, subsystem: Subsystem) {
+        self.assert_role(Role::Pauser);
+
+        match subsystem {
+            Subsystem::Deposits => self.paused.deposits = false,
+            Subsystem::NodeRegistration => self.paused.node_registration = false,
+            Subsystem::CallCreation => self.paused.call_creation = false,
+            Subsystem::CallSettlement => self.paused.call_settlement = false,
+            Subsystem::Withdrawals => {
+                let until = &mut self.paused.withdrawals_until;
+                *until = (*until).min(env::block_timestamp());
+            }
+        }
+
+        Event::SubsystemUnpaused {
+            subsystem,
+            account_id: env::predecessor_account_id(),
+        }
+        .emit();
+    }
+
+    pub fn get_paused(&self) -> &PauseState {
+        &self.paused
+    }
+}
+
+impl Contract {
+    pub(crate) fn is_paused(&self, subsystem: Subsystem) -> bool {
+        match subsystem {
+            Subsystem::Deposits => self.paused.deposits,
+            Subsystem::NodeRegistration => self.paused.node_registration,
+            Subsystem::CallCreation => self.paused.call_creation,
+            Subsystem::CallSettlement => self.paused.call_settlement,
+            Subsystem::Withdrawals => env::block_timestamp() < self.paused.withdrawals_until,
+        }
+    }
+
+    pub(crate) fn assert_not_paused(&self, subsystem: Subsystem) {
+        ensure(
+            !self.is_paused(subsystem),
+            ContractError::Paused { subsystem },
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use crate::*;
+
+/// What an account other than the owner is allowed to do. The owner holds
+/// every role without being listed.
+#[derive(
+    BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Changes the config and the token whitelist, and grants every role
+    /// but `Admin`, which only the owner grants.
+    Admin,
+    /// Ends stuck calls. Nodes are fined through disputes, see
+    /// `resolve_dispute`.
+    FineOperator,
+    /// Withdraws the protocol share to the owner.
+    Treasurer,
+    /// Pauses and unpauses subsystems, see `pause`.
+    Pauser,
+    /// Settles disputes, see `resolve_dispute`.
+    Resolver,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Names the next owner, who takes over once it calls
+    /// `accept_ownership`. Until then the current owner keeps every right
+    /// and can propose someone else.
+    pub fn propose_owner(&mut self, new_owner: AccountId) {
+        self.assert_owner();
+
+        self.proposed_owner = Some(new_owner.clone());
+
+        Event::OwnershipProposed {
+            owner_id: self.owner.clone(),
+            proposed_owner_id: new_owner,
+        }
+        .emit();
+    }
+
+    pub fn accept_ownership(&mut self) {
+        ensure(
+            self.proposed_owner.as_ref() == Some(&env::predecessor_account_id()),
+            ContractError::NotProposedOwner,
+        );
+
+        let previous_owner =
+            std::mem::replace(&mut self.owner, self.proposed_owner.take().unwrap());
+
+        Event::OwnershipTransferred {
+            previous_owner_id: previous_owner,
+            owner_id: self.owner.clone(),
+        }
+        .emit();
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner.clone()
+    }
+
+    pub fn get_proposed_owner(&self) -> Option<AccountId> {
+        self.proposed_owner.clone()
+    }
+
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_can_manage(role);
+
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if !roles.contains(&role) {
+            roles.push(role);
+            self.roles.insert(&account_id, &roles);
+
+            Event::RoleGranted { account_id, role }.emit();
+        }
+    }
+
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_can_manage(role);
+
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if let Some(index) = roles.iter().position(|r| *r == role) {
+            roles.swap_remove(index);
+            if roles.is_empty() {
+                self.roles.remove(&account_id);
+            } else {
+                self.roles.insert(&account_id, &roles);
+            }
+
+            Event::RoleRevoked { account_id, role }.emit();
+        }
+    }
+
+    /// Accounts holding a role, besides the owner.
+    pub fn get_roles(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, Vec<Role>)> {
+        paginate(&self.roles, from_index, limit)
+    }
+}
+
+impl Contract {
+    pub(crate) fn has_role(&self, account_id: &AccountId, role: Role) -> bool {
+        self.owner == *account_id
+            || self
+                .roles
+                .get(account_id)
+                .is_some_and(|roles| roles.contains(&role))
+    }
+
+    pub(crate) fn assert_role(&self, role: Role) {
+        ensure(
+            self.has_role(&env::predecessor_account_id(), role),
+            ContractError::MissingRole { role },
+        );
+    }
+
+    fn assert_owner(&self) {
+        ensure(
+            self.owner == env::predecessor_account_id(),
+            ContractError::NotOwner,
+        );
+    }
+
+    fn assert_can_manage(&self, role: Role) {
+        match role {
+            Role::Admin => self.assert_owner(),
+            _ => self.assert_role(Role::Admin),
+        }
+    }
+}
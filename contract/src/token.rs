@@ -0,0 +1,121 @@
+use crate::*;
+use near_sdk::{ext_contract, PromiseOrValue};
+
+pub const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+
+/// A NEP-141 token clients can pay for minutes in.
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Token {
+    /// Billed per call minute, in the token's smallest unit.
+    pub minute_price: Balance,
+    /// Protocol share collected in this token, what `balance` is for NEAR.
+    pub balance: Balance,
+    /// Fund share collected in this token, what `fund_balance` is for NEAR.
+    pub fund_balance: Balance,
+}
+
+#[ext_contract(ext_ft)]
+#[allow(dead_code)]
+pub trait FungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
+/// Sends `amount` of `token_id` from the contract to `receiver_id`.
+pub(crate) fn ft_transfer(token_id: AccountId, receiver_id: AccountId, amount: Balance) -> Promise {
+    ext_ft::ext(token_id)
+        .with_attached_deposit(1)
+        .with_static_gas(GAS_FOR_FT_TRANSFER)
+        .ft_transfer(receiver_id, U128(amount), None)
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn add_token(&mut self, token_id: AccountId, minute_price: U128) {
+        self.assert_role(Role::Admin);
+        ensure(
+            self.tokens.get(&token_id).is_none(),
+            ContractError::TokenExists,
+        );
+        ensure(minute_price.0 > 0, ContractError::InvalidConfigValue);
+
+        self.tokens.insert(
+            &token_id,
+            &Token {
+                minute_price: minute_price.0,
+                balance: 0,
+                fund_balance: 0,
+            },
+        );
+    }
+
+    pub fn set_token_minute_price(&mut self, token_id: AccountId, minute_price: U128) {
+        self.assert_role(Role::Admin);
+        ensure(minute_price.0 > 0, ContractError::InvalidConfigValue);
+
+        let mut token = self.get_whitelisted_token(&token_id);
+        token.minute_price = minute_price.0;
+        self.tokens.insert(&token_id, &token);
+    }
+
+    pub fn get_tokens(&self) -> Vec<(AccountId, Token)> {
+        self.tokens.to_vec()
+    }
+
+    pub fn get_token(&self, token_id: AccountId) -> Option<Token> {
+        self.tokens.get(&token_id)
+    }
+
+    /// NEP-141 receiver: credits tokens sent with `ft_transfer_call` from a
+    /// whitelisted token to the sender's balance. The sender must already be
+    /// a client, as storage is paid in NEAR by `add_balance`. `msg` is unused.
+    #[allow(unused_variables)]
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_not_paused(Subsystem::Deposits);
+
+        let token_id = env::predecessor_account_id();
+        self.get_whitelisted_token(&token_id);
+
+        let mut client = self
+            .clients
+            .get(&sender_id)
+            .unwrap_or_else(|| ContractError::ClientNotFound.panic());
+
+        let balance = client.balance_mut(Some(&token_id));
+        *balance += amount.0;
+        let deposited_amount = *balance;
+
+        self.clients.insert(&sender_id, &client);
+
+        Event::ClientDeposit {
+            client_id: sender_id,
+            amount,
+            deposited_amount: U128(deposited_amount),
+            token_id: Some(token_id),
+        }
+        .emit();
+
+        PromiseOrValue::Value(U128(0))
+    }
+}
+
+impl Contract {
+    pub(crate) fn get_whitelisted_token(&self, token_id: &AccountId) -> Token {
+        self.tokens
+            .get(token_id)
+            .unwrap_or_else(|| ContractError::TokenNotWhitelisted.panic())
+    }
+
+    /// Price of a call minute in `token_id`, or in NEAR for `None`.
+    pub(crate) fn minute_price(&self, token_id: Option<&AccountId>) -> Balance {
+        match token_id {
+            Some(token_id) => self.get_whitelisted_token(token_id).minute_price,
+            None => self.config.minute_price,
+        }
+    }
+}
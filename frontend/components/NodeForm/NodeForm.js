@@ -7,13 +7,20 @@ import classNames from 'classnames';
 import {observer} from 'mobx-react';
 import Big from 'big.js';
 import {BOATLOAD_OF_GAS} from '../../App';
-import {Box} from '@chakra-ui/react'
+import {Box, Checkbox} from '@chakra-ui/react'
 
 const NodeForm = () => {
   const {currentUser, contract} = appStore
   const [value, setValue] = useState('')
   const [node, setNode] = useState(null)
   const [address, setAddress] = useState('')
+  const [region, setRegion] = useState('')
+  const [version, setVersion] = useState('')
+  const [maxParticipants, setMaxParticipants] = useState('')
+  const [maxConcurrentCalls, setMaxConcurrentCalls] = useState('')
+  const [codecs, setCodecs] = useState('')
+  const [simulcast, setSimulcast] = useState(false)
+  const [svc, setSvc] = useState(false)
 
   useEffect(() => {
       if (currentUser) {
@@ -23,6 +30,13 @@ const NodeForm = () => {
               setNode(node)
               setValue(Big(node.staked_amount).div(10 ** 24))
               setAddress(node.address)
+              setRegion(node.metadata.region)
+              setVersion(node.metadata.version)
+              setMaxParticipants(String(node.metadata.max_participants))
+              setMaxConcurrentCalls(String(node.metadata.max_concurrent_calls))
+              setCodecs(node.metadata.codecs.join(', '))
+              setSimulcast(node.metadata.simulcast)
+              setSvc(node.metadata.svc)
             } else {
               setValue('10')
             }
@@ -37,8 +51,18 @@ const NodeForm = () => {
       return
     }
 
+    const metadata = {
+      region,
+      version,
+      max_participants: Number(maxParticipants),
+      max_concurrent_calls: Number(maxConcurrentCalls),
+      codecs: codecs.split(',').map(c => c.trim()).filter(c => c),
+      simulcast,
+      svc,
+    }
+
     contract.add_node(
-      {address},
+      {address, metadata},
       BOATLOAD_OF_GAS,
       Big(value || '0').times(10 ** 24).toFixed()
     ).then(() => {
@@ -97,6 +121,69 @@ const NodeForm = () => {
         />
       </Box>
 
+      <Box mt={'16px'}>
+        <Input
+          label={isAdd ? 'Enter region' : 'Region'}
+          value={region}
+          onChange={!isAdd ? undefined : setRegion}
+          disabled={!currentUser}
+        />
+      </Box>
+
+      <Box mt={'16px'}>
+        <Input
+          label={isAdd ? 'Enter SFU version' : 'SFU version'}
+          value={version}
+          onChange={!isAdd ? undefined : setVersion}
+          disabled={!currentUser}
+        />
+      </Box>
+
+      <Box mt={'16px'}>
+        <Input
+          label={isAdd ? 'Enter max participants' : 'Max participants'}
+          value={maxParticipants}
+          onChange={!isAdd ? undefined : (v) => setMaxParticipants(v.replace(/[^0-9]+/g, ''))}
+          disabled={!currentUser}
+        />
+      </Box>
+
+      <Box mt={'16px'}>
+        <Input
+          label={isAdd ? 'Enter max concurrent calls' : 'Max concurrent calls'}
+          value={maxConcurrentCalls}
+          onChange={!isAdd ? undefined : (v) => setMaxConcurrentCalls(v.replace(/[^0-9]+/g, ''))}
+          disabled={!currentUser}
+        />
+      </Box>
+
+      <Box mt={'16px'}>
+        <Input
+          label={isAdd ? 'Enter codecs, comma separated' : 'Codecs'}
+          value={codecs}
+          onChange={!isAdd ? undefined : setCodecs}
+          disabled={!currentUser}
+        />
+      </Box>
+
+      <Box mt={'16px'}>
+        <Checkbox
+          isChecked={simulcast}
+          onChange={(e) => setSimulcast(e.target.checked)}
+          isDisabled={!isAdd || !currentUser}
+        >
+          Simulcast
+        </Checkbox>
+        <Checkbox
+          ml={'16px'}
+          isChecked={svc}
+          onChange={(e) => setSvc(e.target.checked)}
+          isDisabled={!isAdd || !currentUser}
+        >
+          SVC
+        </Checkbox>
+      </Box>
+
       <div className={classNames(styles.buttonContainer, (!address) && styles.disabled)}>
         <Button
           text={isAdd ? 'ADD NODE' : 'DELETE NODE'}